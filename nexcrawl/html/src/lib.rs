@@ -1,10 +1,184 @@
+pub mod links;
+pub mod markdown;
+pub mod meta;
+pub mod microdata;
 pub mod minimum_dom_tree;
 pub mod node;
+pub mod parse;
 pub mod preprocess;
 pub mod sanitize;
 pub mod selector;
+pub mod structured;
+mod table;
 
-pub use preprocess::{PreprocessConfig, Preprocessor};
-pub use sanitize::{SanitizeOptions, sanitize_html};
+pub use links::{detect_pagination, extract_absolute_links, extract_links, resolve_url};
+pub use markdown::{LinkStyle, MarkdownConfig, MarkdownEmitter};
+pub use meta::{
+    RobotsDirectives, extract_canonical, extract_meta, extract_metadata, extract_robots_directives,
+};
+pub use microdata::{MicrodataItem, MicrodataValue, extract_microdata};
+pub use parse::{
+    Fragment, parse_document, parse_fragment, parse_html, parse_html_bytes, parse_html_reader,
+};
+pub use preprocess::{
+    EmojiPolicy, EmptyReason, LinkHandling, PreprocessConfig, PreprocessOutcome, Preprocessor,
+    TableHandling, TimeHandling,
+};
+pub use sanitize::{OversizedAttribute, SanitizeOptions, sanitize_html};
 pub use minimum_dom_tree::MinimumDomTree;
-pub use selector::{select, get_selector};
+pub use selector::{
+    Selector, SelectorError, SelectorGenerator, SelectorOptions, get_selector, matches_all,
+    matches_any, remove_matching, select, select_count, select_first, select_in,
+    select_outermost, select_parents, select_with_options, try_select,
+};
+pub use structured::extract_json_ld;
+
+use node::{Handle, Node, NodeData};
+
+/// Sanitize untrusted HTML, then compact it with the preprocessor
+///
+/// Sanitizing comes first, so preprocessing never sees scripts or other removed content. The
+/// result is the same as passing the output of [`sanitize_html`] to
+/// [`Preprocessor::preprocess_html`]; the HTML is still parsed twice, as the sanitizer only
+/// works on strings.
+///
+/// # Examples
+///
+/// ```
+/// use nexcrawl_html::{PreprocessConfig, SanitizeOptions, clean_and_preprocess};
+///
+/// let html = "<div><script>alert(1)</script><p>Hello</p></div>";
+/// let output =
+///     clean_and_preprocess(html, &SanitizeOptions::default(), &PreprocessConfig::default());
+/// assert_eq!(output, "<html><body><div><p>Hello</p></div></body></html>");
+/// ```
+pub fn clean_and_preprocess(
+    html: &str,
+    sanitize: &SanitizeOptions,
+    config: &PreprocessConfig,
+) -> String {
+    let sanitized = sanitize_html(html, sanitize);
+    Preprocessor::new(config.clone()).preprocess_html(&sanitized)
+}
+
+/// Sanitize, preprocess, and query HTML in one go
+///
+/// The stages run in order: the HTML is sanitized with [`sanitize_html`], parsed once, and the
+/// parsed tree is compacted with [`Preprocessor::preprocess_tree`].
+/// The individual stages stay available for advanced use.
+///
+/// # Examples
+///
+/// ```
+/// use nexcrawl_html::{Pipeline, PreprocessConfig};
+///
+/// let (_tree, results) = Pipeline::new()
+///     .preprocess(PreprocessConfig::default())
+///     .run_and_select("<div><p class='intro'>Hello</p></div>", "p");
+/// assert_eq!(results.len(), 1);
+/// assert_eq!(results[0].text_content(), "Hello");
+/// ```
+pub struct Pipeline {
+    sanitize_options: SanitizeOptions,
+    preprocessor: Preprocessor,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self {
+            sanitize_options: SanitizeOptions::default(),
+            preprocessor: Preprocessor::new(PreprocessConfig::default()),
+        }
+    }
+
+    /// Set the options of the sanitization stage
+    pub fn sanitize(mut self, options: SanitizeOptions) -> Self {
+        self.sanitize_options = options;
+        self
+    }
+
+    /// Set the config of the preprocessing stage
+    pub fn preprocess(mut self, config: PreprocessConfig) -> Self {
+        self.preprocessor = Preprocessor::new(config);
+        self
+    }
+
+    /// Run the pipeline, returning the preprocessed tree
+    ///
+    /// An empty `Document` node is returned if nothing is left after preprocessing.
+    pub fn run(&self, html: &str) -> Handle {
+        let sanitized = sanitize_html(html, &self.sanitize_options);
+        let dom = parse_html(&sanitized);
+        self.preprocessor
+            .preprocess_tree(&dom.tree)
+            .unwrap_or_else(|| Node::new(NodeData::Document))
+    }
+
+    /// Run the pipeline and select the matching nodes of the preprocessed tree
+    ///
+    /// The root of the tree is returned along with the matches: dropping it clears the
+    /// children of every node in the tree, so it must outlive the matches.
+    pub fn run_and_select(&self, html: &str, selector: &str) -> (Handle, Vec<Handle>) {
+        let tree = self.run(html);
+        let results = select(&tree, selector);
+        (tree, results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::serialize_to_string;
+
+    #[test]
+    fn test_pipeline_run() {
+        let html = "<div><script>alert(1)</script><custom><p>Hello <b>world</b></p></custom></div>";
+        let tree = Pipeline::new().run(html);
+        assert_eq!(
+            serialize_to_string(&tree).unwrap(),
+            "<html><body><div><p>Hello world</p></div></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_run_and_select() {
+        let html = "<ul><li class='item'>One</li><li class='item'>Two</li></ul>";
+        let (tree, results) = Pipeline::new()
+            .sanitize(SanitizeOptions::default())
+            .run_and_select(html, "li.item");
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].text_content(), "One");
+        assert_eq!(results[1].children.borrow().len(), 1);
+        assert!(results[1].get_parent().is_some());
+        assert!(results[0].is_descendant_of(&tree));
+    }
+
+    #[test]
+    fn test_clean_and_preprocess() {
+        let html = r#"<html><head><script>track()</script></head><body>
+            <div><div><p onclick="steal()">Hello   <b>big</b> world</p></div>
+            <script>alert(1)</script><img src="x" onerror="alert(2)"></div></body></html>"#;
+        let sanitize = SanitizeOptions::default();
+        let config = PreprocessConfig::default();
+
+        let output = clean_and_preprocess(html, &sanitize, &config);
+        assert_eq!(output, "<html><body><div><p>Hello big world</p></div></body></html>");
+        assert_eq!(
+            output,
+            Preprocessor::new(PreprocessConfig::default())
+                .preprocess_html(&sanitize_html(html, &sanitize))
+        );
+    }
+
+    #[test]
+    fn test_pipeline_run_empty() {
+        let tree = Pipeline::new().run("<script>alert(1)</script>");
+        assert!(tree.children.borrow().is_empty());
+    }
+}
@@ -0,0 +1,323 @@
+//! Extract the URLs a page links to
+
+use std::collections::HashSet;
+
+use url::Url;
+
+use crate::node::{Handle, NodeData, text_content};
+
+/// Get the unique URLs the tree links to, in order of first appearance
+///
+/// Collects `href` of `<a>` and `<area>` elements. With `include_embedded`, also collects
+/// `src` of `<img>`, `<script>` and `<iframe>` elements, i.e. resources the page embeds
+/// rather than navigates to.
+///
+/// URLs are returned verbatim, apart from trimming surrounding whitespace as browsers do, so
+/// relative URLs stay relative and fragment-only (`#top`) or `javascript:` links are
+/// included. Empty values are skipped.
+pub fn extract_links(tree: &Handle, include_embedded: bool) -> Vec<String> {
+    let mut links = Vec::new();
+    let mut seen = HashSet::new();
+    collect_links(tree, include_embedded, &mut seen, &mut links);
+    links
+}
+
+/// Get the unique URLs the tree links to as absolute URLs, in order of first appearance
+///
+/// Like [`extract_links`], but each URL is resolved with [`resolve_url`]. The base is the
+/// `href` of the first `<base>` element of the document, itself resolved against `base_url`
+/// (the URL the page was fetched from), or `base_url` if there is none. URLs that can't be
+/// resolved are skipped, and URLs are deduplicated after resolution.
+pub fn extract_absolute_links(
+    tree: &Handle,
+    base_url: &str,
+    include_embedded: bool,
+) -> Vec<String> {
+    let base = match find_base_href(tree) {
+        Some(href) => resolve_url(base_url, &href).unwrap_or_else(|| base_url.to_string()),
+        None => base_url.to_string(),
+    };
+
+    let mut seen = HashSet::new();
+    extract_links(tree, include_embedded)
+        .iter()
+        .filter_map(|link| resolve_url(&base, link))
+        .filter(|url| seen.insert(url.clone()))
+        .collect()
+}
+
+/// Find the most likely URL of the next page of a paginated listing
+///
+/// Candidates are tried in this order, and the first one with a non-empty `href` wins:
+/// 1. `<link rel="next">`, the explicit marker most sites put in the `<head>`,
+/// 2. `<a rel="next">`,
+/// 3. an `<a>` whose text (or `aria-label`, if it has no text) is `Next`, `Next page`,
+///    `›` or `»`, case-insensitively and optionally with trailing arrows like `Next »`.
+///
+/// The URL is returned verbatim; see [`resolve_url`] to make it absolute.
+pub fn detect_pagination(tree: &Handle) -> Option<String> {
+    let mut links = Vec::new();
+    let mut anchors = Vec::new();
+    collect_pagination_candidates(tree, &mut links, &mut anchors);
+
+    links
+        .iter()
+        .chain(anchors.iter())
+        .filter(|node| has_rel_next(node))
+        .find_map(href)
+        .or_else(|| {
+            anchors
+                .iter()
+                .filter(|anchor| is_next_label(&anchor_label(anchor)))
+                .find_map(href)
+        })
+}
+
+/// Collect the `<link>` and `<a>` elements, in document order
+fn collect_pagination_candidates(
+    node: &Handle,
+    links: &mut Vec<Handle>,
+    anchors: &mut Vec<Handle>,
+) {
+    for child in node.children.borrow().iter() {
+        if let NodeData::Element { name, .. } = &child.data {
+            match name.local.as_ref() {
+                "link" => links.push(child.clone()),
+                "a" => anchors.push(child.clone()),
+                _ => {}
+            }
+        }
+        collect_pagination_candidates(child, links, anchors);
+    }
+}
+
+fn has_rel_next(node: &Handle) -> bool {
+    node.get_attribute("rel").is_some_and(|rel| {
+        rel.split_ascii_whitespace()
+            .any(|token| token.eq_ignore_ascii_case("next"))
+    })
+}
+
+/// Get the trimmed, non-empty `href` of the element
+fn href(node: &Handle) -> Option<String> {
+    let href = node.get_attribute("href")?;
+    let href = href.trim();
+    (!href.is_empty()).then(|| href.to_string())
+}
+
+/// Get the text of an anchor with collapsed whitespace, or its `aria-label` if it has no text
+fn anchor_label(anchor: &Handle) -> String {
+    let text = text_content(anchor)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if text.is_empty() {
+        anchor.get_attribute("aria-label").unwrap_or_default()
+    } else {
+        text
+    }
+}
+
+/// Check whether the label of a link reads as "next page"
+fn is_next_label(label: &str) -> bool {
+    const ARROWS: &[char] = &['›', '»', '→', '>'];
+    let label = label.trim();
+    if !label.is_empty() && label.chars().all(|c| matches!(c, '›' | '»')) {
+        return true;
+    }
+    let words = label.trim_end_matches(|c: char| ARROWS.contains(&c) || c.is_whitespace());
+    words.eq_ignore_ascii_case("next") || words.eq_ignore_ascii_case("next page")
+}
+
+/// Resolve a possibly relative URL against an absolute base URL
+///
+/// Follows the URL standard, like a browser: the reference can replace the scheme,
+/// authority, path or query of the base, and `.`/`..` path segments are normalized.
+/// Returns `None` if `base` is not an absolute URL or the result is not a valid URL.
+///
+/// # Examples
+///
+/// ```
+/// use nexcrawl_html::links::resolve_url;
+///
+/// assert_eq!(
+///     resolve_url("https://example.com/a/b/page.html", "../c?x=1").as_deref(),
+///     Some("https://example.com/a/c?x=1")
+/// );
+/// assert_eq!(resolve_url("not a url", "page.html"), None);
+/// ```
+pub fn resolve_url(base: &str, relative: &str) -> Option<String> {
+    let base = Url::parse(base).ok()?;
+    base.join(relative.trim()).ok().map(String::from)
+}
+
+/// Get the `href` of the first `<base>` element that has one
+fn find_base_href(node: &Handle) -> Option<String> {
+    if let NodeData::Element { name, attrs, .. } = &node.data
+        && name.local.as_ref() == "base"
+        && let Some(attr) = attrs
+            .borrow()
+            .iter()
+            .find(|attr| attr.name.local.as_ref() == "href")
+    {
+        return Some(attr.value.to_string());
+    }
+    node.children.borrow().iter().find_map(find_base_href)
+}
+
+fn collect_links(
+    node: &Handle,
+    include_embedded: bool,
+    seen: &mut HashSet<String>,
+    links: &mut Vec<String>,
+) {
+    if let NodeData::Element { name, attrs, .. } = &node.data {
+        let attribute = match name.local.as_ref() {
+            "a" | "area" => Some("href"),
+            "img" | "script" | "iframe" if include_embedded => Some("src"),
+            _ => None,
+        };
+        if let Some(attribute) = attribute
+            && let Some(attr) = attrs
+                .borrow()
+                .iter()
+                .find(|attr| attr.name.local.as_ref() == attribute)
+        {
+            let url = attr.value.trim();
+            if !url.is_empty() && seen.insert(url.to_string()) {
+                links.push(url.to_string());
+            }
+        }
+    }
+
+    for child in node.children.borrow().iter() {
+        collect_links(child, include_embedded, seen, links);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_html;
+
+    #[test]
+    fn test_extract_links() {
+        let html = r##"
+            <script src="/app.js"></script>
+            <a href="/next"> Next </a>
+            <a href=" /next ">Again</a>
+            <a>No href</a><a href="">Empty</a>
+            <map><area href="https://example.com/area"></map>
+            <img src="logo.png"><iframe src="https://video.example/embed"></iframe>
+            <a href="#top">Top</a>
+        "##;
+        let dom = parse_html(html);
+
+        assert_eq!(
+            extract_links(&dom.tree, false),
+            vec!["/next", "https://example.com/area", "#top"]
+        );
+        assert_eq!(
+            extract_links(&dom.tree, true),
+            vec![
+                "/app.js",
+                "/next",
+                "https://example.com/area",
+                "logo.png",
+                "https://video.example/embed",
+                "#top"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_url() {
+        let base = "https://example.com/docs/guide/intro.html?lang=en";
+        let resolve = |relative| resolve_url(base, relative);
+
+        assert_eq!(
+            resolve("setup.html").as_deref(),
+            Some("https://example.com/docs/guide/setup.html")
+        );
+        assert_eq!(
+            resolve("../../about/./team").as_deref(),
+            Some("https://example.com/about/team")
+        );
+        assert_eq!(
+            resolve("/root").as_deref(),
+            Some("https://example.com/root")
+        );
+        assert_eq!(
+            resolve("//cdn.example.net/x.js").as_deref(),
+            Some("https://cdn.example.net/x.js")
+        );
+        assert_eq!(
+            resolve("?page=2").as_deref(),
+            Some("https://example.com/docs/guide/intro.html?page=2")
+        );
+        assert_eq!(
+            resolve("#top").as_deref(),
+            Some("https://example.com/docs/guide/intro.html?lang=en#top")
+        );
+        assert_eq!(
+            resolve("mailto:me@example.com").as_deref(),
+            Some("mailto:me@example.com")
+        );
+
+        assert_eq!(resolve_url("/relative/base", "page.html"), None);
+        assert_eq!(resolve("http://[invalid"), None);
+    }
+
+    #[test]
+    fn test_extract_absolute_links() {
+        let html = r#"<head><base href="/blog/"></head>
+            <a href="post-1">One</a><a href="/blog/post-1">Same</a>
+            <a href="https://other.example/">Other</a><a href="http://[bad">Bad</a>"#;
+        let dom = parse_html(html);
+        assert_eq!(
+            extract_absolute_links(&dom.tree, "https://example.com/index.html", false),
+            vec!["https://example.com/blog/post-1", "https://other.example/"]
+        );
+
+        let dom = parse_html(r#"<a href="post-1">One</a>"#);
+        assert_eq!(
+            extract_absolute_links(&dom.tree, "https://example.com/news/", false),
+            vec!["https://example.com/news/post-1"]
+        );
+    }
+
+    #[test]
+    fn test_detect_pagination_rel_next() {
+        let html = r#"<head><link rel="prev" href="/page/1"><link rel="next" href="/page/3"></head>
+            <body><a href="/page/4">Next</a></body>"#;
+        let dom = parse_html(html);
+        assert_eq!(detect_pagination(&dom.tree).as_deref(), Some("/page/3"));
+
+        let dom =
+            parse_html(r#"<a href="/page/9">Next</a><a rel="nofollow Next" href="/page/3">3</a>"#);
+        assert_eq!(detect_pagination(&dom.tree).as_deref(), Some("/page/3"));
+    }
+
+    #[test]
+    fn test_detect_pagination_text() {
+        let detect = |html: &str| detect_pagination(&parse_html(html).tree);
+
+        assert_eq!(
+            detect(r#"<a href="/1">1</a><a href="/2"> Next  page </a>"#).as_deref(),
+            Some("/2")
+        );
+        assert_eq!(
+            detect(r#"<a href="/next-article">Next article</a><a href="/2">next »</a>"#).as_deref(),
+            Some("/2")
+        );
+        assert_eq!(detect(r#"<a href="/2">›</a>"#).as_deref(), Some("/2"));
+        assert_eq!(
+            detect(r#"<a href="/2" aria-label="Next"><svg></svg></a>"#).as_deref(),
+            Some("/2")
+        );
+        assert_eq!(
+            detect(r#"<a href="">Next</a><a href="/about">About</a>"#),
+            None
+        );
+    }
+}
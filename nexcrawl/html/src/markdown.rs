@@ -0,0 +1,659 @@
+//! Convert HTML to Markdown
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+
+use crate::node::{Handle, NodeData};
+use crate::parse::parse_html;
+use crate::table::{pipe_table, table_grid};
+
+/// Tags whose contents are never emitted
+const SKIPPED_TAGS: &[&str] = &[
+    "head", "script", "style", "noscript", "template", "iframe", "object", "embed", "svg", "canvas",
+];
+
+/// Tags that start a new block of text
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "html",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "ul",
+];
+
+/// How links are written in the Markdown output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LinkStyle {
+    /// `[text](url)`
+    #[default]
+    Inline,
+    /// `[text][1]`, with a `[1]: url` list at the end of the output.
+    /// Repeated URLs share the same reference number.
+    Reference,
+}
+
+#[derive(Debug, Clone)]
+pub struct MarkdownConfig {
+    pub link_style: LinkStyle,
+
+    /// Number of spaces each level of a nested list is indented by. Defaults to 2.
+    pub list_indent: usize,
+
+    /// Render form fields as readable text instead of dropping the data in their attributes.
+    ///
+    /// - A `<label>` is written as `Label: value`, or `[x] Label` for checkboxes and radio
+    ///   buttons. Its field is the element whose `id` matches the label's `for` attribute,
+    ///   or else the first field inside the label.
+    /// - A field without a label is written as its value.
+    /// - The value of an `<input>` or `<textarea>` is its current value, or its placeholder in
+    ///   parentheses if it is empty. A `<select>` shows its selected option (or first option).
+    /// - Hidden inputs and buttons are skipped.
+    pub render_forms: bool,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> Self {
+        Self {
+            link_style: LinkStyle::default(),
+            list_indent: 2,
+            render_forms: false,
+        }
+    }
+}
+
+/// Convert HTML into Markdown that is easier for LLMs to read
+///
+/// Supports headings, paragraphs, emphasis, inline code, code blocks, links, images, lists,
+/// block quotes and tables.
+///
+/// Tables are emitted as GitHub-flavored markdown tables, like [`TableHandling::Markdown`]
+/// does. The header row comes from `<thead>`, or from the first row if the table has no
+/// `<thead>`. A cell spanning several columns (`colspan`) is followed by empty cells, and
+/// `rowspan` is ignored; short rows are padded with empty cells.
+///
+/// [`TableHandling::Markdown`]: crate::preprocess::TableHandling::Markdown
+#[derive(Default)]
+pub struct MarkdownEmitter {
+    config: MarkdownConfig,
+
+    /// URLs of the links referenced so far, in order of first appearance
+    references: RefCell<Vec<String>>,
+
+    /// Whether the emitter is writing the text of a `<label>`, whose field is written after it
+    in_label: Cell<bool>,
+
+    /// Ids referred to by a `<label for>` of the tree being converted, whose fields are written
+    /// with their label
+    labeled_ids: RefCell<HashSet<String>>,
+}
+
+impl MarkdownEmitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: MarkdownConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Parse the HTML and convert it into Markdown
+    pub fn to_markdown(&self, html: &str) -> String {
+        if html.is_empty() {
+            return String::new();
+        }
+
+        let dom = parse_html(html);
+        self.node_to_markdown(&dom.tree)
+    }
+
+    /// Convert a node and its descendants into Markdown
+    pub fn node_to_markdown(&self, node: &Handle) -> String {
+        let mut labeled_ids = HashSet::new();
+        collect_label_targets(&node.root(), &mut labeled_ids);
+        self.labeled_ids.replace(labeled_ids);
+
+        let mut out = String::new();
+        self.emit(node, &mut out);
+        let mut out = finish(&out);
+        self.labeled_ids.take();
+
+        let references = self.references.take();
+        if !references.is_empty() {
+            if !out.is_empty() {
+                out.push_str("\n\n");
+            }
+            let list = references
+                .iter()
+                .enumerate()
+                .map(|(i, url)| format!("[{}]: {}", i + 1, url))
+                .collect::<Vec<_>>();
+            out.push_str(&list.join("\n"));
+        }
+        out
+    }
+
+    fn emit(&self, node: &Handle, out: &mut String) {
+        match &node.data {
+            NodeData::Text { text } => push_text(out, &text.borrow()),
+            NodeData::Document => self.emit_children(node, out),
+            NodeData::Element { name, .. } => {
+                let tag = name.local.as_ref();
+                if SKIPPED_TAGS.contains(&tag) {
+                    return;
+                }
+
+                let is_block = BLOCK_TAGS.contains(&tag);
+                if is_block {
+                    ensure_blank_line(out);
+                }
+
+                match tag {
+                    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                        let level = tag[1..].parse::<usize>().unwrap_or(1);
+                        out.push_str(&"#".repeat(level));
+                        out.push(' ');
+                        out.push_str(&self.inline(node));
+                    }
+                    "strong" | "b" => self.emit_wrapped(node, "**", out),
+                    "em" | "i" => self.emit_wrapped(node, "*", out),
+                    "del" | "s" | "strike" => self.emit_wrapped(node, "~~", out),
+                    "code" => self.emit_wrapped(node, "`", out),
+                    "br" => out.push('\n'),
+                    "hr" => out.push_str("---"),
+                    "a" => self.emit_link(node, out),
+                    "img" => {
+                        if let Some(src) = node.get_attribute("src") {
+                            let alt = node.get_attribute("alt").unwrap_or_default();
+                            out.push_str(&format!("![{}]({})", alt, src));
+                        }
+                    }
+                    "pre" => {
+                        let mut code = String::new();
+                        collect_raw_text(node, &mut code);
+                        out.push_str("```\n");
+                        out.push_str(code.trim_end_matches('\n'));
+                        out.push_str("\n```");
+                    }
+                    "blockquote" => {
+                        let quoted = self.node_to_markdown_children(node);
+                        for (i, line) in quoted.lines().enumerate() {
+                            if i > 0 {
+                                out.push('\n');
+                            }
+                            out.push('>');
+                            if !line.is_empty() {
+                                out.push(' ');
+                                out.push_str(line);
+                            }
+                        }
+                    }
+                    "label" if self.config.render_forms => self.emit_label(node, out),
+                    "input" | "select" | "textarea" if self.config.render_forms => {
+                        self.emit_field(node, out)
+                    }
+                    "ul" | "ol" => self.emit_list(node, tag == "ol", out),
+                    "table" => self.emit_table(node, out),
+                    _ => self.emit_children(node, out),
+                }
+
+                if is_block {
+                    ensure_blank_line(out);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn emit_children(&self, node: &Handle, out: &mut String) {
+        for child in node.children.borrow().iter() {
+            self.emit(child, out);
+        }
+    }
+
+    /// Convert the children of a node into a standalone Markdown string
+    fn node_to_markdown_children(&self, node: &Handle) -> String {
+        let mut out = String::new();
+        self.emit_children(node, &mut out);
+        finish(&out)
+    }
+
+    /// Convert the children of a node into a single line of Markdown
+    fn inline(&self, node: &Handle) -> String {
+        self.node_to_markdown_children(node)
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn emit_wrapped(&self, node: &Handle, marker: &str, out: &mut String) {
+        let content = self.inline(node);
+        if content.is_empty() {
+            return;
+        }
+        out.push_str(marker);
+        out.push_str(&content);
+        out.push_str(marker);
+    }
+
+    fn emit_link(&self, node: &Handle, out: &mut String) {
+        let content = self.inline(node);
+        match node.get_attribute("href") {
+            Some(href) => match self.config.link_style {
+                LinkStyle::Inline => out.push_str(&format!("[{}]({})", content, href)),
+                LinkStyle::Reference => {
+                    let number = self.reference_number(href);
+                    out.push_str(&format!("[{}][{}]", content, number));
+                }
+            },
+            None => out.push_str(&content),
+        }
+    }
+
+    /// Get the reference number of a URL, registering it if it is new
+    fn reference_number(&self, url: String) -> usize {
+        let mut references = self.references.borrow_mut();
+        match references.iter().position(|existing| *existing == url) {
+            Some(index) => index + 1,
+            None => {
+                references.push(url);
+                references.len()
+            }
+        }
+    }
+
+    fn emit_label(&self, label: &Handle, out: &mut String) {
+        self.in_label.set(true);
+        let text = self.inline(label);
+        self.in_label.set(false);
+
+        let value = label_field(label).and_then(|field| field_value(&field));
+        push_separator(out);
+        match value {
+            Some(value) if value.starts_with('[') => {
+                out.push_str(&value);
+                if !text.is_empty() {
+                    out.push(' ');
+                    out.push_str(&text);
+                }
+            }
+            Some(value) if !text.is_empty() => out.push_str(&format!("{}: {}", text, value)),
+            Some(value) => out.push_str(&value),
+            None => out.push_str(&text),
+        }
+    }
+
+    /// Write a field that is not written as part of its label
+    fn emit_field(&self, field: &Handle, out: &mut String) {
+        if self.in_label.get() || self.has_label_for(field) {
+            return;
+        }
+        if let Some(value) = field_value(field) {
+            push_separator(out);
+            out.push_str(&value);
+        }
+    }
+
+    /// Whether a `<label for>` in the same tree refers to the field
+    fn has_label_for(&self, field: &Handle) -> bool {
+        field.get_attribute("id").is_some_and(|id| self.labeled_ids.borrow().contains(&id))
+    }
+
+    fn emit_list(&self, node: &Handle, ordered: bool, out: &mut String) {
+        let mut index = 1;
+        for child in node.children.borrow().iter() {
+            if !matches!(&child.data, NodeData::Element { name, .. } if name.local.as_ref() == "li")
+            {
+                continue;
+            }
+
+            if ordered {
+                out.push_str(&format!("{}. ", index));
+            } else {
+                out.push_str("- ");
+            }
+            out.push_str(&self.list_item(child));
+            index += 1;
+        }
+    }
+
+    /// Convert a list item into its text line followed by its nested lists, indented
+    fn list_item(&self, item: &Handle) -> String {
+        let mut text = String::new();
+        let mut nested = String::new();
+        for child in item.children.borrow().iter() {
+            match &child.data {
+                NodeData::Element { name, .. } if matches!(name.local.as_ref(), "ul" | "ol") => {
+                    self.emit_list(child, name.local.as_ref() == "ol", &mut nested)
+                }
+                _ => self.emit(child, &mut text),
+            }
+        }
+
+        let mut out = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        out.push('\n');
+        let indent = " ".repeat(self.config.list_indent);
+        for line in nested.lines() {
+            out.push_str(&indent);
+            out.push_str(line);
+            out.push('\n');
+        }
+        out
+    }
+
+    fn emit_table(&self, table: &Handle, out: &mut String) {
+        let grid = table_grid(table, &|cell| self.inline(cell));
+        if !grid.is_empty() {
+            out.push_str(&pipe_table(&grid));
+            out.push('\n');
+        }
+    }
+}
+
+/// Whether the node is an `<input>`, `<select>` or `<textarea>`
+fn is_field(node: &Handle) -> bool {
+    matches!(&node.data, NodeData::Element { name, .. } if matches!(name.local.as_ref(), "input" | "select" | "textarea"))
+}
+
+/// Find the field of a label: the element referenced by `for`, or else the first nested field
+fn label_field(label: &Handle) -> Option<Handle> {
+    if let Some(id) = label.get_attribute("for") {
+        return find_descendant(&label.root(), &|node| {
+            is_field(node) && node.get_attribute("id").as_ref() == Some(&id)
+        });
+    }
+    find_descendant(label, &is_field)
+}
+
+/// Collect the `for` of the `<label>` descendants of the node
+fn collect_label_targets(node: &Handle, ids: &mut HashSet<String>) {
+    for child in node.children.borrow().iter() {
+        if matches!(&child.data, NodeData::Element { name, .. } if name.local.as_ref() == "label")
+            && let Some(id) = child.get_attribute("for")
+        {
+            ids.insert(id);
+        }
+        collect_label_targets(child, ids);
+    }
+}
+
+/// Find the first descendant of the node, in document order, satisfying `pred`
+fn find_descendant(node: &Handle, pred: &dyn Fn(&Handle) -> bool) -> Option<Handle> {
+    for child in node.children.borrow().iter() {
+        if pred(child) {
+            return Some(child.clone());
+        }
+        if let Some(found) = find_descendant(child, pred) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+/// Render the current value of a form field, or `None` if it has nothing to show
+fn field_value(field: &Handle) -> Option<String> {
+    let NodeData::Element { name, .. } = &field.data else {
+        return None;
+    };
+
+    let (value, placeholder) = match name.local.as_ref() {
+        "input" => {
+            let input_type = field.get_attribute("type").unwrap_or_default().to_ascii_lowercase();
+            match input_type.as_str() {
+                "hidden" | "submit" | "reset" | "button" | "image" => return None,
+                "checkbox" | "radio" => {
+                    let checked = field.get_attribute("checked").is_some();
+                    return Some(if checked { "[x]" } else { "[ ]" }.to_string());
+                }
+                _ => (field.get_attribute("value"), field.get_attribute("placeholder")),
+            }
+        }
+        "textarea" => {
+            let mut text = String::new();
+            collect_raw_text(field, &mut text);
+            (Some(text), field.get_attribute("placeholder"))
+        }
+        "select" => {
+            let is_option = |node: &Handle| {
+                matches!(&node.data, NodeData::Element { name, .. } if name.local.as_ref() == "option")
+            };
+            let option = find_descendant(field, &|node| {
+                is_option(node) && node.get_attribute("selected").is_some()
+            })
+            .or_else(|| find_descendant(field, &is_option))?;
+            let mut text = String::new();
+            collect_raw_text(&option, &mut text);
+            (Some(text), None)
+        }
+        _ => return None,
+    };
+
+    let normalize = |text: String| text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if let Some(value) = value.map(normalize).filter(|value| !value.is_empty()) {
+        return Some(value);
+    }
+    placeholder
+        .map(normalize)
+        .filter(|placeholder| !placeholder.is_empty())
+        .map(|placeholder| format!("({})", placeholder))
+}
+
+/// Append text, collapsing whitespace the way a browser would render it
+fn push_text(out: &mut String, text: &str) {
+    let starts_with_space = text.starts_with(char::is_whitespace);
+    let ends_with_space = text.ends_with(char::is_whitespace);
+    let words = text.split_whitespace().collect::<Vec<_>>();
+
+    if words.is_empty() {
+        if starts_with_space {
+            push_separator(out);
+        }
+        return;
+    }
+
+    if starts_with_space {
+        push_separator(out);
+    }
+    out.push_str(&words.join(" "));
+    if ends_with_space {
+        out.push(' ');
+    }
+}
+
+/// Append a single space unless the output is at the start of a line or already spaced
+fn push_separator(out: &mut String) {
+    if !out.is_empty() && !out.ends_with([' ', '\n']) {
+        out.push(' ');
+    }
+}
+
+/// Make sure the output ends with an empty line (or is empty)
+fn ensure_blank_line(out: &mut String) {
+    while out.ends_with(' ') {
+        out.pop();
+    }
+    if out.is_empty() || out.ends_with("\n\n") {
+        return;
+    }
+    if out.ends_with('\n') {
+        out.push('\n');
+    } else {
+        out.push_str("\n\n");
+    }
+}
+
+/// Append the text of all descendants without normalizing whitespace
+fn collect_raw_text(node: &Handle, out: &mut String) {
+    for child in node.children.borrow().iter() {
+        match &child.data {
+            NodeData::Text { text } => out.push_str(&text.borrow()),
+            _ => collect_raw_text(child, out),
+        }
+    }
+}
+
+/// Remove trailing spaces and redundant empty lines
+fn finish(out: &str) -> String {
+    let mut result = String::new();
+    let mut blank_lines = 0;
+    for line in out.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        if !result.is_empty() {
+            result.push_str(if blank_lines > 0 { "\n\n" } else { "\n" });
+        }
+        blank_lines = 0;
+        result.push_str(line);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_markdown() {
+        let html = "<html><head><title>Title</title></head><body>\
+            <h1>Heading</h1>\
+            <p>Text with <b>bold</b> and <a href=\"https://example.com\">a link</a>.</p>\
+            <ul><li>One</li><li>Two</li></ul>\
+            </body></html>";
+        let markdown = MarkdownEmitter::new().to_markdown(html);
+        assert_eq!(
+            markdown,
+            "# Heading\n\nText with **bold** and [a link](https://example.com).\n\n- One\n- Two"
+        );
+    }
+
+    #[test]
+    fn test_nested_lists() {
+        let html = "<ol>\
+            <li>First<ul><li>Bullet<ol><li>Deep</li><li>Deeper</li></ol></li><li>Second bullet</li></ul></li>\
+            <li>Second</li>\
+            </ol><p>After</p>";
+        let markdown = MarkdownEmitter::new().to_markdown(html);
+        assert_eq!(
+            markdown,
+            "1. First\n  - Bullet\n    1. Deep\n    2. Deeper\n  - Second bullet\n2. Second\n\nAfter"
+        );
+
+        let markdown = MarkdownEmitter::with_config(MarkdownConfig {
+            list_indent: 4,
+            ..Default::default()
+        })
+        .to_markdown("<ul><li>One<ul><li>Two</li></ul></li></ul>");
+        assert_eq!(markdown, "- One\n    - Two");
+    }
+
+    #[test]
+    fn test_table_with_thead() {
+        let html = "<table>\
+            <thead><tr><th>Name</th><th>Price</th></tr></thead>\
+            <tbody><tr><td>Apple</td><td>$1</td></tr><tr><td>Pear</td><td>$2</td></tr></tbody>\
+            </table>";
+        let markdown = MarkdownEmitter::new().to_markdown(html);
+        assert_eq!(
+            markdown,
+            "| Name | Price |\n| --- | --- |\n| Apple | $1 |\n| Pear | $2 |"
+        );
+    }
+
+    #[test]
+    fn test_table_without_thead() {
+        let html = "<p>Before</p><table>\
+            <tr><td>a | b</td><td>c</td></tr>\
+            <tr><td>d</td></tr>\
+            </table><p>After</p>";
+        let markdown = MarkdownEmitter::new().to_markdown(html);
+        assert_eq!(
+            markdown,
+            "Before\n\n| a \\| b | c |\n| --- | --- |\n| d |  |\n\nAfter"
+        );
+
+        // A cell spanning several columns is followed by empty cells
+        let html = "<table><tr><td>a</td><td colspan=2>b</td></tr><tr><td>c</td></tr></table>";
+        assert_eq!(
+            MarkdownEmitter::new().to_markdown(html),
+            "| a | b |  |\n| --- | --- | --- |\n| c |  |  |"
+        );
+    }
+
+    #[test]
+    fn test_reference_links() {
+        let html = "<p>See <a href=\"https://a.com\">A</a>, <a href=\"https://b.com\">B</a> \
+            and <a href=\"https://a.com\">A again</a>.</p><p><a>No href</a></p>";
+        let emitter = MarkdownEmitter::with_config(MarkdownConfig {
+            link_style: LinkStyle::Reference,
+            ..Default::default()
+        });
+        let expected = "See [A][1], [B][2] and [A again][1].\n\nNo href\n\n\
+            [1]: https://a.com\n[2]: https://b.com";
+        assert_eq!(emitter.to_markdown(html), expected);
+
+        // References do not leak into the next conversion
+        assert_eq!(emitter.to_markdown("<p>Plain</p>"), "Plain");
+    }
+
+    #[test]
+    fn test_render_forms() {
+        let html = "<form>\
+            <p><label for=\"name\">Name</label> <input id=\"name\" value=\"Ada\"></p>\
+            <p><label>Email <input type=\"email\" placeholder=\"you@example.com\"></label></p>\
+            <p><label><input type=\"checkbox\" checked> Subscribe</label></p>\
+            <p><label for=\"size\">Size</label><select id=\"size\">\
+                <option>Small</option><option selected>Large</option></select></p>\
+            <p><textarea>Hello\nthere</textarea><input type=\"hidden\" value=\"token\"></p>\
+            </form>";
+
+        let emitter = MarkdownEmitter::with_config(MarkdownConfig {
+            render_forms: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            emitter.to_markdown(html),
+            "Name: Ada\n\nEmail: (you@example.com)\n\n[x] Subscribe\n\nSize: Large\n\nHello there"
+        );
+
+        // Without the option, only the text content is kept
+        assert_eq!(
+            MarkdownEmitter::new().to_markdown(html),
+            "Name\n\nEmail\n\nSubscribe\n\nSizeSmallLarge\n\nHello there"
+        );
+    }
+}
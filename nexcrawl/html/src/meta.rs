@@ -0,0 +1,319 @@
+//! Extract page metadata from the `<head>`
+//!
+//! These functions read elements that the preprocessor and sanitizer remove (`<meta>` and
+//! `<link>`), so run them on the parsed tree before preprocessing.
+
+use std::collections::HashMap;
+
+use crate::node::{Handle, NodeData, normalized_text};
+
+/// Collect the `<meta>` tags of the document into a map of key to `content`
+///
+/// Tags are looked up in the `<head>`, or in the whole tree if there is no `<head>`. Keys are
+/// ASCII-lowercased and come from:
+/// - `name`, e.g. `description`, `viewport` or `twitter:card`,
+/// - `property`, used by Open Graph tags like `og:title`,
+/// - `http-equiv`, prefixed as `http-equiv:<value>`, e.g. `http-equiv:refresh`,
+/// - `charset`, which has no `content`: its key is `charset` and its value is the charset.
+///
+/// A tag with several of these attributes uses the `charset`, then the first one of this list.
+/// If a key appears several times, the first value wins. Tags without a key or a `content` are
+/// skipped.
+pub fn extract_meta(tree: &Handle) -> HashMap<String, String> {
+    let mut meta = HashMap::new();
+    collect_meta(tree, true, &mut meta);
+    meta
+}
+
+/// Collect the title and the descriptive `<meta>` tags of the document into a map of key to
+/// value, e.g. to keep that context next to the preprocessed content
+///
+/// The text of the `<title>` is stored under `title`, and the `<meta>` tags are collected like
+/// [`extract_meta`] does, except for the technical `http-equiv` and `charset` tags, which are
+/// skipped. The `<title>` wins over a `<meta name="title">`.
+pub fn extract_metadata(tree: &Handle) -> HashMap<String, String> {
+    let mut metadata = HashMap::new();
+    if let Some(title) = head_elements(tree, "title").first() {
+        let title = normalized_text(title, &[]);
+        if !title.is_empty() {
+            metadata.insert("title".to_string(), title);
+        }
+    }
+    collect_meta(tree, false, &mut metadata);
+    metadata
+}
+
+/// Get the canonical URL of the page
+///
+/// Returns the `href` of the first `<link rel="canonical">` of the `<head>`, falling back to
+/// the `content` of `<meta property="og:url">`. The URL is returned as written, so it may be
+/// relative; see [`resolve_url`](crate::links::resolve_url).
+pub fn extract_canonical(tree: &Handle) -> Option<String> {
+    let canonical = head_elements(tree, "link").into_iter().find_map(|link| {
+        let rel = link.get_attribute("rel")?;
+        if !rel
+            .split_ascii_whitespace()
+            .any(|token| token.eq_ignore_ascii_case("canonical"))
+        {
+            return None;
+        }
+        non_empty(link.get_attribute("href")?)
+    });
+
+    canonical.or_else(|| {
+        head_elements(tree, "meta").into_iter().find_map(|meta| {
+            let property = meta.get_attribute("property")?;
+            if !property.trim().eq_ignore_ascii_case("og:url") {
+                return None;
+            }
+            non_empty(meta.get_attribute("content")?)
+        })
+    })
+}
+
+/// The page-level directives for crawlers, see [`extract_robots_directives`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RobotsDirectives {
+    /// Don't show the page in search results
+    pub noindex: bool,
+    /// Don't follow the links of the page
+    pub nofollow: bool,
+    /// Don't show a cached copy of the page
+    pub noarchive: bool,
+    /// Don't show a snippet of the page in search results
+    pub nosnippet: bool,
+    /// Don't index the images of the page
+    pub noimageindex: bool,
+}
+
+/// Read the directives of the `<meta name="robots">` and `<meta name="googlebot">` tags
+///
+/// The `content` of each tag is a comma-separated list of directives, matched
+/// case-insensitively. `none` stands for `noindex, nofollow`. Restrictions add up: with
+/// several tags, a directive is set if any tag sets it, and `index`/`follow` never lift a
+/// restriction. Unknown directives (e.g. `max-snippet:50`) are ignored.
+pub fn extract_robots_directives(tree: &Handle) -> RobotsDirectives {
+    let mut directives = RobotsDirectives::default();
+    for meta in head_elements(tree, "meta") {
+        let Some(name) = meta.get_attribute("name") else {
+            continue;
+        };
+        let name = name.trim();
+        if !(name.eq_ignore_ascii_case("robots") || name.eq_ignore_ascii_case("googlebot")) {
+            continue;
+        }
+
+        let content = meta.get_attribute("content").unwrap_or_default();
+        for directive in content.split(',') {
+            match directive.trim().to_ascii_lowercase().as_str() {
+                "noindex" => directives.noindex = true,
+                "nofollow" => directives.nofollow = true,
+                "none" => {
+                    directives.noindex = true;
+                    directives.nofollow = true;
+                }
+                "noarchive" => directives.noarchive = true,
+                "nosnippet" => directives.nosnippet = true,
+                "noimageindex" => directives.noimageindex = true,
+                _ => {}
+            }
+        }
+    }
+    directives
+}
+
+/// Trim the value, returning `None` if nothing is left
+fn non_empty(value: String) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Add the `<meta>` tags of the document to `meta`, keyed as described in [`extract_meta`]
+///
+/// The `http-equiv` and `charset` tags are only added if `technical` is set. Keys already in
+/// `meta` are kept.
+fn collect_meta(tree: &Handle, technical: bool, meta: &mut HashMap<String, String>) {
+    for element in head_elements(tree, "meta") {
+        let (key, value) = if let Some(charset) = element.get_attribute("charset") {
+            if !technical {
+                continue;
+            }
+            ("charset".to_string(), charset)
+        } else {
+            let key = if let Some(name) = element.get_attribute("name") {
+                name
+            } else if let Some(property) = element.get_attribute("property") {
+                property
+            } else if let Some(http_equiv) = element.get_attribute("http-equiv") {
+                if !technical {
+                    continue;
+                }
+                format!("http-equiv:{}", http_equiv)
+            } else {
+                continue;
+            };
+            let Some(content) = element.get_attribute("content") else {
+                continue;
+            };
+            (key, content)
+        };
+
+        let key = key.trim().to_ascii_lowercase();
+        if !key.is_empty() {
+            meta.entry(key).or_insert(value);
+        }
+    }
+}
+
+/// Get the elements named `tag` of the `<head>`, or of the whole tree if there is no
+/// `<head>`, in document order
+fn head_elements(tree: &Handle, tag: &str) -> Vec<Handle> {
+    let head = find_element(tree, "head").unwrap_or_else(|| tree.clone());
+    let mut elements = Vec::new();
+    collect_elements(&head, tag, &mut elements);
+    elements
+}
+
+fn find_element(node: &Handle, tag: &str) -> Option<Handle> {
+    node.children
+        .borrow()
+        .iter()
+        .find_map(|child| match &child.data {
+            NodeData::Element { name, .. } if name.local.as_ref() == tag => Some(child.clone()),
+            _ => find_element(child, tag),
+        })
+}
+
+fn collect_elements(node: &Handle, tag: &str, elements: &mut Vec<Handle>) {
+    for child in node.children.borrow().iter() {
+        if matches!(&child.data, NodeData::Element { name, .. } if name.local.as_ref() == tag) {
+            elements.push(child.clone());
+        }
+        collect_elements(child, tag, elements);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_html;
+
+    #[test]
+    fn test_extract_metadata() {
+        let html = r#"<html><head>
+            <meta charset="utf-8">
+            <title> Widgets  &amp; more </title>
+            <meta name="description" content="A page about widgets">
+            <meta property="og:title" content="Widgets (OG)">
+            <meta name="keywords" property="og:keywords" content="widgets">
+            <meta name="OG:Description" content="Widgets for everyone">
+            <meta http-equiv="refresh" content="30">
+            <meta name="title" content="Ignored, the title element wins">
+            </head><body><p>Text</p></body></html>"#;
+        let dom = parse_html(html);
+        let metadata = extract_metadata(&dom.tree);
+
+        assert_eq!(metadata["title"], "Widgets & more");
+        assert_eq!(metadata["description"], "A page about widgets");
+        assert_eq!(metadata["og:title"], "Widgets (OG)");
+        assert_eq!(metadata["og:description"], "Widgets for everyone");
+        // `name` wins over `property`, like in `extract_meta`
+        assert_eq!(metadata["keywords"], "widgets");
+        assert_eq!(metadata.len(), 5);
+
+        assert!(extract_metadata(&parse_html("<p>No head content</p>").tree).is_empty());
+    }
+
+    #[test]
+    fn test_extract_meta() {
+        let html = r#"<html><head>
+            <meta charset="utf-8">
+            <meta name="Description" content="A page about widgets">
+            <meta name="description" content="Ignored duplicate">
+            <meta name="viewport" content="width=device-width">
+            <meta property="og:title" content="Widgets">
+            <meta name="twitter:card" content="summary">
+            <meta http-equiv="Refresh" content="30">
+            <meta name="no-content">
+            </head><body><meta name="body" content="Outside head"></body></html>"#;
+        let dom = parse_html(html);
+        let meta = extract_meta(&dom.tree);
+
+        assert_eq!(meta["og:title"], "Widgets");
+        assert_eq!(meta["description"], "A page about widgets");
+        assert_eq!(meta["charset"], "utf-8");
+        assert_eq!(meta["viewport"], "width=device-width");
+        assert_eq!(meta["twitter:card"], "summary");
+        assert_eq!(meta["http-equiv:refresh"], "30");
+        assert!(!meta.contains_key("no-content"));
+        assert!(!meta.contains_key("body"));
+        assert_eq!(meta.len(), 6);
+    }
+
+    #[test]
+    fn test_extract_canonical() {
+        let html = r#"<head>
+            <link rel="stylesheet" href="/style.css">
+            <meta property="og:url" content="https://example.com/og">
+            <link rel="Canonical" href=" https://example.com/article ">
+            </head>"#;
+        let dom = parse_html(html);
+        assert_eq!(
+            extract_canonical(&dom.tree).as_deref(),
+            Some("https://example.com/article")
+        );
+
+        // `og:url` is the fallback
+        let dom = parse_html(r#"<meta property="og:url" content="https://example.com/og">"#);
+        assert_eq!(
+            extract_canonical(&dom.tree).as_deref(),
+            Some("https://example.com/og")
+        );
+
+        let dom = parse_html(r#"<link rel="canonical" href=""><p>No canonical</p>"#);
+        assert_eq!(extract_canonical(&dom.tree), None);
+    }
+
+    #[test]
+    fn test_extract_robots_directives() {
+        let directives = |html: &str| extract_robots_directives(&parse_html(html).tree);
+
+        assert_eq!(directives("<p>No tags</p>"), RobotsDirectives::default());
+        assert_eq!(
+            directives(r#"<meta name="robots" content="NoIndex, nofollow">"#),
+            RobotsDirectives {
+                noindex: true,
+                nofollow: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            directives(r#"<meta name="ROBOTS" content="none">"#),
+            RobotsDirectives {
+                noindex: true,
+                nofollow: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            directives(r#"<meta name="robots" content="index, follow, max-snippet:50">"#),
+            RobotsDirectives::default()
+        );
+
+        // Several tags add up, and `index` doesn't lift an earlier `noindex`
+        assert_eq!(
+            directives(
+                r#"<meta name="robots" content="noindex,noarchive">
+                <meta name="googlebot" content="index, nosnippet , noimageindex">
+                <meta name="other" content="nofollow">"#
+            ),
+            RobotsDirectives {
+                noindex: true,
+                nofollow: false,
+                noarchive: true,
+                nosnippet: true,
+                noimageindex: true,
+            }
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Extract schema.org style microdata
+//!
+//! Supports the core of the [HTML microdata] model:
+//! - Every element with `itemscope` and without `itemprop` is a top-level item
+//! - `itemtype` and `itemid` of an item
+//! - `itemprop` descendants, including several space-separated names on one element
+//! - Nested items: an `itemprop` element with `itemscope` becomes a nested item
+//!
+//! `itemref` is not supported, so properties must be descendants of their item.
+//!
+//! [HTML microdata]: https://html.spec.whatwg.org/multipage/microdata.html
+
+use crate::node::{Handle, NodeData, text_content};
+
+/// An item: an element with `itemscope` and its properties
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MicrodataItem {
+    /// The types from `itemtype`, e.g. `https://schema.org/Product`
+    pub item_type: Vec<String>,
+    /// The global identifier from `itemid`
+    pub id: Option<String>,
+    /// The properties as (name, value), in document order
+    pub properties: Vec<(String, MicrodataValue)>,
+}
+
+impl MicrodataItem {
+    /// Get the first value of the property `name`
+    pub fn get(&self, name: &str) -> Option<&MicrodataValue> {
+        self.properties
+            .iter()
+            .find(|(property, _)| property == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// The value of a microdata property
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MicrodataValue {
+    Text(String),
+    Item(MicrodataItem),
+}
+
+/// Extract all top-level microdata items of the tree
+///
+/// Property values are taken from the attribute the element type defines (`content` of
+/// `<meta>`, `href` of `<a>`, `src` of `<img>`, `datetime` of `<time>`, ...), falling back to
+/// the element's text content with whitespace collapsed.
+pub fn extract_microdata(tree: &Handle) -> Vec<MicrodataItem> {
+    let mut items = Vec::new();
+    collect_items(tree, &mut items);
+    items
+}
+
+fn collect_items(node: &Handle, items: &mut Vec<MicrodataItem>) {
+    if has_attribute(node, "itemscope") && !has_attribute(node, "itemprop") {
+        items.push(parse_item(node));
+    }
+    for child in node.children.borrow().iter() {
+        collect_items(child, items);
+    }
+}
+
+fn parse_item(node: &Handle) -> MicrodataItem {
+    let mut item = MicrodataItem {
+        item_type: node
+            .get_attribute("itemtype")
+            .map(|types| types.split_ascii_whitespace().map(String::from).collect())
+            .unwrap_or_default(),
+        id: node.get_attribute("itemid"),
+        properties: Vec::new(),
+    };
+    collect_properties(node, &mut item.properties);
+    item
+}
+
+/// Collect the properties below `node`, without entering nested items
+fn collect_properties(node: &Handle, properties: &mut Vec<(String, MicrodataValue)>) {
+    for child in node.children.borrow().iter() {
+        if !matches!(child.data, NodeData::Element { .. }) {
+            continue;
+        }
+
+        let is_item = has_attribute(child, "itemscope");
+        if let Some(names) = child.get_attribute("itemprop") {
+            let value = if is_item {
+                MicrodataValue::Item(parse_item(child))
+            } else {
+                MicrodataValue::Text(property_value(child))
+            };
+            for name in names.split_ascii_whitespace() {
+                properties.push((name.to_string(), value.clone()));
+            }
+        }
+
+        // The properties of a nested item belong to that item
+        if !is_item {
+            collect_properties(child, properties);
+        }
+    }
+}
+
+/// Get the value of a property element that is not an item
+fn property_value(node: &Handle) -> String {
+    let NodeData::Element { name, .. } = &node.data else {
+        return String::new();
+    };
+
+    let attribute = match name.local.as_ref() {
+        "meta" => Some("content"),
+        "audio" | "embed" | "iframe" | "img" | "source" | "track" | "video" => Some("src"),
+        "a" | "area" | "link" => Some("href"),
+        "object" => Some("data"),
+        "data" | "meter" => Some("value"),
+        "time" => Some("datetime"),
+        _ => None,
+    };
+
+    attribute
+        .and_then(|attribute| node.get_attribute(attribute))
+        .unwrap_or_else(|| {
+            text_content(node)
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+}
+
+fn has_attribute(node: &Handle, name: &str) -> bool {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .any(|attr| attr.name.local.as_ref() == name),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_html;
+
+    #[test]
+    fn test_extract_microdata() {
+        let html = r#"
+            <div itemscope itemtype="https://schema.org/Product" itemid="urn:sku:1">
+                <h1 itemprop="name">  Blue   Widget </h1>
+                <img itemprop="image" src="/widget.png">
+                <div itemprop="offers" itemscope itemtype="https://schema.org/Offer">
+                    <meta itemprop="priceCurrency" content="USD">
+                    <span itemprop="price">9.99</span>
+                </div>
+                <p>Released <time itemprop="releaseDate dateCreated" datetime="2024-01-02">Jan 2</time></p>
+            </div>
+            <p itemscope><span itemprop="note">Second</span></p>
+        "#;
+        let dom = parse_html(html);
+        let items = extract_microdata(&dom.tree);
+        assert_eq!(items.len(), 2);
+
+        let product = &items[0];
+        assert_eq!(product.item_type, vec!["https://schema.org/Product"]);
+        assert_eq!(product.id.as_deref(), Some("urn:sku:1"));
+
+        let text = |value: &str| MicrodataValue::Text(value.to_string());
+        let offer = MicrodataItem {
+            item_type: vec!["https://schema.org/Offer".to_string()],
+            id: None,
+            properties: vec![
+                ("priceCurrency".to_string(), text("USD")),
+                ("price".to_string(), text("9.99")),
+            ],
+        };
+        assert_eq!(
+            product.properties,
+            vec![
+                ("name".to_string(), text("Blue Widget")),
+                ("image".to_string(), text("/widget.png")),
+                ("offers".to_string(), MicrodataValue::Item(offer)),
+                ("releaseDate".to_string(), text("2024-01-02")),
+                ("dateCreated".to_string(), text("2024-01-02")),
+            ]
+        );
+
+        assert_eq!(items[1].get("note"), Some(&text("Second")));
+        assert!(items[1].item_type.is_empty());
+    }
+}
@@ -3,10 +3,17 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
 
-use crate::node::{Handle, NodeData};
+use std::rc::Rc;
+
+use crate::node::{BLOCK_TAGS, Handle, Node, NodeData};
 
 pub struct MinimumDomTree {
-    cache: RefCell<HashMap<Handle, String>>,
+    /// Text of the visited nodes, keyed by node identity.
+    /// `Handle`'s own `Hash` is structural, so it can't tell apart e.g. two `<p>` elements.
+    cache: RefCell<HashMap<*const Node, String>>,
+
+    /// Joins the text of a block element (see [`BLOCK_TAGS`]) to its siblings instead of a space
+    block_separator: Option<String>,
 }
 
 impl Default for MinimumDomTree {
@@ -19,9 +26,26 @@ impl MinimumDomTree {
     pub fn new() -> Self {
         Self {
             cache: RefCell::new(HashMap::new()),
+            block_separator: None,
         }
     }
 
+    /// Separate the text of block elements such as `<p>` from their siblings with `separator`
+    ///
+    /// E.g. with `"\n"`, the text of an article keeps one paragraph per line, like text copied
+    /// from the page. The target and node texts are compared token by token, split on
+    /// whitespace, so a separator that is not whitespace must also appear in the target.
+    pub fn block_separator(mut self, separator: &str) -> Self {
+        self.block_separator = Some(separator.to_string());
+        self
+    }
+
+    /// Find the smallest set of nodes of `tree` whose text makes up `text`
+    ///
+    /// Returns `None` if the words of `text` are not found in order in the tree. The search only
+    /// goes down into a node whose text contains `text`, or is contained in it. So if `text`
+    /// skips a word of a node, e.g. "the" of a `<div>` with the text "the test tree", nothing
+    /// under that node is selected and the returned nodes only cover part of `text`.
     pub fn build(&self, tree: &Handle, text: &str) -> Option<Vec<Handle>> {
         // If the text cannot be extracted from the tree, return empty
         if text.is_empty() || !self.is_subset(text, self.get_text(tree).as_str()) {
@@ -71,7 +95,7 @@ impl MinimumDomTree {
         // If all children are included in the nodes, they are selected with parent node instead of themselves
         let mut can_merge = true;
         for child in node.children.borrow().iter() {
-            if !nodes.contains(child) {
+            if !nodes.iter().any(|n| n.same_node(child)) {
                 can_merge = false;
                 break;
             }
@@ -81,7 +105,7 @@ impl MinimumDomTree {
             // Remove all children from the nodes
             let mut new_nodes = Vec::new();
             for elem in nodes.iter() {
-                if !node.children.borrow().contains(elem) {
+                if !node.children.borrow().iter().any(|child| child.same_node(elem)) {
                     new_nodes.push(elem.clone());
                 }
             }
@@ -93,22 +117,34 @@ impl MinimumDomTree {
     }
 
     fn get_text(&self, node: &Handle) -> String {
-        if let Some(text) = self.cache.borrow().get(node) {
+        if let Some(text) = self.cache.borrow().get(&Rc::as_ptr(node)) {
             return text.clone();
         }
 
         match &node.data {
             NodeData::Text { text } => text.borrow().clone().to_string(),
             _ => {
-                let mut texts = Vec::new();
-                for child in node.children.borrow().iter() {
-                    let text = self.get_text(child);
-                    texts.push(text);
+                let mut joined_text = String::new();
+                let mut previous_is_block = false;
+                for (i, child) in node.children.borrow().iter().enumerate() {
+                    let is_block = matches!(
+                        &child.data,
+                        NodeData::Element { name, .. } if BLOCK_TAGS.contains(&name.local.as_ref())
+                    );
+                    if i > 0 {
+                        match &self.block_separator {
+                            Some(separator) if is_block || previous_is_block => {
+                                joined_text.push_str(separator)
+                            }
+                            _ => joined_text.push(' '),
+                        }
+                    }
+                    joined_text.push_str(&self.get_text(child));
+                    previous_is_block = is_block;
                 }
-                let joined_text = texts.join(" ");
                 self.cache
                     .borrow_mut()
-                    .insert(node.clone(), joined_text.clone());
+                    .insert(Rc::as_ptr(node), joined_text.clone());
                 joined_text
             }
         }
@@ -141,10 +177,6 @@ mod tests {
 
     #[test]
     fn test_minimum_dom_tree() {
-        use html5ever::QualName;
-        use html5ever::tendril::StrTendril;
-        use std::str::FromStr;
-
         // Create a DOM tree with height = 4
         // Structure:
         // div (root)
@@ -158,79 +190,37 @@ mod tests {
         //           └── "tree" (height 3)
 
         // Create root div element
-        let root = Node::new(NodeData::Element {
-            name: QualName::new(None, html5ever::ns!(html), html5ever::local_name!("div")),
-            attrs: std::cell::RefCell::new(Vec::new()),
-            template_contents: std::cell::RefCell::new(None),
-            mathml_annotation_xml_integration_point: false,
-        });
+        let root = Node::new_element("div", &[]);
 
         // Create first p element
-        let p_elem = Node::new(NodeData::Element {
-            name: QualName::new(None, html5ever::ns!(html), html5ever::local_name!("p")),
-            attrs: std::cell::RefCell::new(Vec::new()),
-            template_contents: std::cell::RefCell::new(None),
-            mathml_annotation_xml_integration_point: false,
-        });
+        let p_elem = Node::new_element("p", &[]);
 
         // Create span element with "Hello" text
-        let span_elem = Node::new(NodeData::Element {
-            name: QualName::new(None, html5ever::ns!(html), html5ever::local_name!("span")),
-            attrs: std::cell::RefCell::new(Vec::new()),
-            template_contents: std::cell::RefCell::new(None),
-            mathml_annotation_xml_integration_point: false,
-        });
+        let span_elem = Node::new_element("span", &[]);
 
-        let hello_text = Node::new(NodeData::Text {
-            text: std::cell::RefCell::new(StrTendril::from_str("Hello").unwrap()),
-        });
+        let hello_text = Node::new_text("Hello".to_string());
 
         // Create b element with "world" text (height 3)
-        let b_elem = Node::new(NodeData::Element {
-            name: QualName::new(None, html5ever::ns!(html), html5ever::local_name!("b")),
-            attrs: std::cell::RefCell::new(Vec::new()),
-            template_contents: std::cell::RefCell::new(None),
-            mathml_annotation_xml_integration_point: false,
-        });
+        let b_elem = Node::new_element("b", &[]);
 
-        let world_text = Node::new(NodeData::Text {
-            text: std::cell::RefCell::new(StrTendril::from_str("world").unwrap()),
-        });
+        let world_text = Node::new_text("world".to_string());
 
         // Create "from" text node
-        let from_text = Node::new(NodeData::Text {
-            text: std::cell::RefCell::new(StrTendril::from_str("from").unwrap()),
-        });
+        let from_text = Node::new_text("from".to_string());
 
         // Create second div element
-        let div2_elem = Node::new(NodeData::Element {
-            name: QualName::new(None, html5ever::ns!(html), html5ever::local_name!("div")),
-            attrs: std::cell::RefCell::new(Vec::new()),
-            template_contents: std::cell::RefCell::new(None),
-            mathml_annotation_xml_integration_point: false,
-        });
+        let div2_elem = Node::new_element("div", &[]);
 
         // Create "the" text node
-        let the_text = Node::new(NodeData::Text {
-            text: std::cell::RefCell::new(StrTendril::from_str("the").unwrap()),
-        });
+        let the_text = Node::new_text("the".to_string());
 
         // Create em element with "test" text
-        let em_elem = Node::new(NodeData::Element {
-            name: QualName::new(None, html5ever::ns!(html), html5ever::local_name!("em")),
-            attrs: std::cell::RefCell::new(Vec::new()),
-            template_contents: std::cell::RefCell::new(None),
-            mathml_annotation_xml_integration_point: false,
-        });
+        let em_elem = Node::new_element("em", &[]);
 
-        let test_text = Node::new(NodeData::Text {
-            text: std::cell::RefCell::new(StrTendril::from_str("test").unwrap()),
-        });
+        let test_text = Node::new_text("test".to_string());
 
         // Create "tree" text node (height 3)
-        let tree_text = Node::new(NodeData::Text {
-            text: std::cell::RefCell::new(StrTendril::from_str("tree").unwrap()),
-        });
+        let tree_text = Node::new_text("tree".to_string());
 
         // Build the tree structure
         // Set parent relationships and add children
@@ -275,26 +265,92 @@ mod tests {
 
         // Test the minimum DOM tree extraction
         let min_dom_tree = MinimumDomTree::new();
-        let target_text = "Hello world from test tree"; // "the" is not included
-
-        let result = min_dom_tree.build(&root, target_text);
-
-        // The result should not be None and should contain the root node
-        // since it contains all the text
-        assert!(result.is_some());
-        let nodes = result.unwrap();
-        assert!(!nodes.is_empty());
+        let extract = |target_text: &str| {
+            let nodes = min_dom_tree.build(&root, target_text).unwrap();
+
+            // Verify that all text can be extracted from the returned nodes
+            let mut extracted_texts = Vec::new();
+            for node in &nodes {
+                let text = min_dom_tree.get_text(node);
+                extracted_texts.push(text);
+            }
+            (nodes, extracted_texts.join(" "))
+        };
+
+        // The whole text is selected with the root
+        let (nodes, extracted_text) = extract("Hello world from the test tree");
+        assert_eq!(nodes.len(), 1);
+        assert!(nodes[0].same_node(&root));
+        assert_eq!(extracted_text, "Hello world from the test tree");
+
+        // The <p> and its descendants are merged into the <p>
+        let (nodes, extracted_text) = extract("Hello world from");
+        assert_eq!(nodes.len(), 1);
+        assert!(nodes[0].same_node(&p_elem));
+        assert_eq!(extracted_text, "Hello world from");
+
+        // "the" is not included, so only the <em> is selected from the second div
+        let (nodes, extracted_text) = extract("test tree");
+        assert_eq!(nodes.len(), 1);
+        assert!(nodes[0].same_node(&em_elem));
+        assert_eq!(extracted_text, "test tree");
+
+        // "the" is skipped, so the second div is pruned and "test tree" is not selected
+        let (nodes, extracted_text) = extract("Hello world from test tree");
+        assert_eq!(nodes.len(), 1);
+        assert!(nodes[0].same_node(&p_elem));
+        assert_eq!(extracted_text, "Hello world from");
+
+        // Text that is not in the tree
+        assert!(min_dom_tree.build(&root, "Hello tree world").is_none());
+    }
 
-        // Verify that all text can be extracted from the returned nodes
-        let mut extracted_texts = Vec::new();
-        for node in &nodes {
-            let text = min_dom_tree.get_text(node);
-            extracted_texts.push(text);
+    #[test]
+    fn test_structurally_equal_siblings() {
+        // div
+        //   ├── b "x"
+        //   └── b "z"
+        let root = Node::new_element("div", &[]);
+        for text in ["x", "z"] {
+            let b = Node::new_element("b", &[]);
+            let text = Node::new_text(text.to_string());
+            text.parent.set(Some(Rc::downgrade(&b)));
+            b.children.borrow_mut().push(text);
+            b.parent.set(Some(Rc::downgrade(&root)));
+            root.children.borrow_mut().push(b);
         }
-        let extracted_text = extracted_texts.join(" ");
 
-        // Check that the extracted text is the same as the target text
-        assert_eq!(extracted_text, target_text);
+        // The second <b> equals the first structurally, but must not be selected with it
+        let nodes = MinimumDomTree::new().build(&root, "x").unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert!(nodes[0].same_node(&root.children.borrow()[0]));
+    }
+
+    #[test]
+    fn test_block_separator() {
+        let dom = crate::parse::parse_html(
+            "<article><p>First paragraph.</p><p>Second <b>one</b></p>Tail<div>Other</div></article>",
+        );
+        let article = crate::selector::select(&dom.tree, "article")[0].clone();
+
+        // Texts keep their own whitespace, and are joined with a space
+        assert_eq!(
+            MinimumDomTree::new().get_text(&article),
+            "First paragraph. Second  one Tail Other"
+        );
+        let min_dom_tree = MinimumDomTree::new().block_separator("\n");
+        assert_eq!(
+            min_dom_tree.get_text(&article),
+            "First paragraph.\nSecond  one\nTail\nOther"
+        );
+
+        let nodes = min_dom_tree
+            .build(&article, "First paragraph.\nSecond one")
+            .unwrap();
+        let paragraphs = crate::selector::select(&article, "p");
+        assert_eq!(nodes.len(), 2);
+        assert!(nodes[0].same_node(&paragraphs[0]));
+        assert!(nodes[1].same_node(&paragraphs[1]));
     }
 
     #[test]
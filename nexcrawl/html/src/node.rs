@@ -29,19 +29,23 @@
 
 use std::borrow::Cow;
 use std::cell::{Cell, RefCell};
-use std::collections::{HashSet, VecDeque};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::default::Default;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::io;
 use std::mem;
+use std::ops::Range;
 use std::rc::{Rc, Weak};
 use std::str::FromStr;
 
 use html5ever::interface::{ElementFlags, NodeOrText, QuirksMode, TreeSink};
 use html5ever::serialize::{Serialize, SerializeOpts, Serializer, TraversalScope, serialize};
 use html5ever::tendril::StrTendril;
-use html5ever::{Attribute, ExpandedName, QualName};
+use html5ever::{Attribute, ExpandedName, LocalName, Namespace, QualName, ns};
+
+use crate::selector::{SelectorOptions, select_with_options};
 
 /// Reference to a DOM node.
 pub type Handle = Rc<Node>;
@@ -143,6 +147,11 @@ impl Hash for NodeData {
 }
 
 /// A DOM node.
+///
+/// `PartialEq`, `Eq` and `Hash` are *structural*: they only compare the node's own
+/// [`NodeData`] (tag, attributes, text...), not its identity or its children. Two different
+/// `<p>` elements without attributes are equal. Use [`Node::same_node`] (or [`Rc::ptr_eq`])
+/// to check whether two handles point to the same node.
 pub struct Node {
     /// Parent node.
     pub parent: Cell<Option<WeakHandle>>,
@@ -168,6 +177,35 @@ impl Node {
         })
     }
 
+    /// Create a new element in the HTML namespace
+    ///
+    /// Attributes are given as `(name, value)` pairs and have no namespace.
+    pub fn new_element(tag: &str, attrs: &[(&str, &str)]) -> Rc<Self> {
+        Node::new_element_ns(ns!(html), tag, attrs)
+    }
+
+    /// Create a new element in the given namespace, e.g. `ns!(svg)` or `ns!(mathml)`
+    pub fn new_element_ns(ns: Namespace, tag: &str, attrs: &[(&str, &str)]) -> Rc<Self> {
+        let attrs = attrs
+            .iter()
+            .map(|(name, value)| Attribute {
+                name: QualName::new(None, ns!(), LocalName::from(*name)),
+                value: StrTendril::from_slice(value),
+            })
+            .collect();
+
+        Node::new(NodeData::Element {
+            name: QualName::new(None, ns, LocalName::from(tag)),
+            attrs: RefCell::new(attrs),
+            template_contents: RefCell::new(if tag == "template" {
+                Some(Node::new(NodeData::Document))
+            } else {
+                None
+            }),
+            mathml_annotation_xml_integration_point: false,
+        })
+    }
+
     /// Deep copy this node and all its descendants with fresh references
     pub fn deep_copy(self: &Rc<Self>) -> Rc<Self> {
         // Create a new node with the same data
@@ -222,6 +260,160 @@ impl Node {
 
         new_node
     }
+
+    /// Replace the content of a text node
+    ///
+    /// Returns `false` without changing anything if this is not a text node.
+    pub fn set_text(&self, new_text: &str) -> bool {
+        match self.data {
+            NodeData::Text { ref text } => {
+                *text.borrow_mut() = StrTendril::from_slice(new_text);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Check whether `other` is this very node, as opposed to a structurally equal one
+    pub fn same_node(&self, other: &Handle) -> bool {
+        std::ptr::eq(self, Rc::as_ptr(other))
+    }
+
+    /// Get the parent of this node, if it has one that is still alive
+    pub fn get_parent(&self) -> Option<Handle> {
+        let weak = self.parent.take();
+        let parent = weak.as_ref().and_then(Weak::upgrade);
+        self.parent.set(weak);
+        parent
+    }
+
+    /// Get the root of the tree this node is in, i.e. the `Document` or the root of a detached
+    /// subtree
+    ///
+    /// The walk up the tree stops at the first dropped parent, returning the highest node that
+    /// is still reachable. A node without a parent is its own root.
+    pub fn root(self: &Rc<Self>) -> Handle {
+        let mut current = self.clone();
+        while let Some(parent) = current.get_parent() {
+            current = parent;
+        }
+        current
+    }
+
+    /// Check whether this node is a (strict) descendant of `possible_ancestor`
+    ///
+    /// The walk up the tree stops at the first dropped parent.
+    pub fn is_descendant_of(&self, possible_ancestor: &Handle) -> bool {
+        let mut current = self.get_parent();
+        while let Some(node) = current {
+            if Rc::ptr_eq(&node, possible_ancestor) {
+                return true;
+            }
+            current = node.get_parent();
+        }
+        false
+    }
+
+    /// Check whether this node is a (strict) ancestor of `possible_descendant`
+    pub fn is_ancestor_of(&self, possible_descendant: &Handle) -> bool {
+        let mut current = possible_descendant.get_parent();
+        while let Some(node) = current {
+            if std::ptr::eq(Rc::as_ptr(&node), self) {
+                return true;
+            }
+            current = node.get_parent();
+        }
+        false
+    }
+
+    /// Get the value of the attribute with the given local name
+    ///
+    /// Returns `None` if the attribute is missing or the node is not an element.
+    pub fn get_attribute(&self, name: &str) -> Option<String> {
+        match &self.data {
+            NodeData::Element { attrs, .. } => attrs
+                .borrow()
+                .iter()
+                .find(|attr| attr.name.local.as_ref() == name)
+                .map(|attr| attr.value.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Set the value of the attribute with the given local name, adding it if it is missing
+    ///
+    /// Does nothing if the node is not an element.
+    pub fn set_attribute(&self, name: &str, value: &str) {
+        let NodeData::Element { attrs, .. } = &self.data else {
+            return;
+        };
+        let mut attrs = attrs.borrow_mut();
+        match attrs.iter_mut().find(|attr| attr.name.local.as_ref() == name) {
+            Some(attr) => attr.value = StrTendril::from_slice(value),
+            None => attrs.push(Attribute {
+                name: QualName::new(None, ns!(), LocalName::from(name)),
+                value: StrTendril::from_slice(value),
+            }),
+        }
+    }
+
+    /// Remove the attribute with the given local name
+    ///
+    /// Returns whether the attribute was present; always `false` for non-element nodes.
+    pub fn remove_attribute(&self, name: &str) -> bool {
+        let NodeData::Element { attrs, .. } = &self.data else {
+            return false;
+        };
+        let mut attrs = attrs.borrow_mut();
+        let len = attrs.len();
+        attrs.retain(|attr| attr.name.local.as_ref() != name);
+        attrs.len() != len
+    }
+
+    /// Check whether the whitespace-separated `class` attribute contains `class`
+    ///
+    /// The comparison is case-sensitive; see [`SelectorOptions`](crate::SelectorOptions) for
+    /// quirks mode matching.
+    pub fn has_class(&self, class: &str) -> bool {
+        self.get_attribute("class")
+            .is_some_and(|classes| classes.split_ascii_whitespace().any(|c| c == class))
+    }
+
+    /// Concatenate the text of every descendant text node, like the DOM `textContent`
+    ///
+    /// Text runs are joined as is, so `<p>Hello<b>world</b></p>` gives `Helloworld`. This is
+    /// the same as the free function [`text_content`].
+    pub fn text_content(self: &Rc<Self>) -> String {
+        text_content(self)
+    }
+
+    /// Join the trimmed text of every descendant text node with `separator`
+    ///
+    /// Whitespace-only text nodes are skipped, so `<p>Hello<b>world</b></p>` gives
+    /// `Hello world` with a `" "` separator. With `skip_scripts`, the contents of `<script>`
+    /// and `<style>` elements are left out.
+    pub fn text_content_separated(self: &Rc<Self>, separator: &str, skip_scripts: bool) -> String {
+        let mut runs = Vec::new();
+        collect_text_runs(self, skip_scripts, &mut runs);
+        runs.join(separator)
+    }
+}
+
+fn collect_text_runs(node: &Handle, skip_scripts: bool, runs: &mut Vec<String>) {
+    for child in node.children.borrow().iter() {
+        match &child.data {
+            NodeData::Text { text } => {
+                let text = text.borrow();
+                let text = text.trim();
+                if !text.is_empty() {
+                    runs.push(text.to_string());
+                }
+            }
+            NodeData::Element { name, .. }
+                if skip_scripts && matches!(name.local.as_ref(), "script" | "style") => {}
+            _ => collect_text_runs(child, skip_scripts, runs),
+        }
+    }
 }
 
 impl Drop for Node {
@@ -229,11 +421,12 @@ impl Drop for Node {
         let mut nodes = mem::take(&mut *self.children.borrow_mut());
         while let Some(node) = nodes.pop() {
             let children = mem::take(&mut *node.children.borrow_mut());
-            nodes.extend(children.into_iter());
+            nodes.extend(children);
         }
     }
 }
 
+/// Structural equality; see [`Node::same_node`] for identity
 impl PartialEq for Node {
     fn eq(&self, other: &Self) -> bool {
         self.data == other.data
@@ -265,25 +458,155 @@ fn append(new_parent: &Handle, child: Handle) {
     new_parent.children.borrow_mut().push(child);
 }
 
-/// If the node has a parent, get it and this node's position in its children
-fn get_parent_and_index(target: &Handle) -> Option<(Handle, usize)> {
-    if let Some(weak) = target.parent.take() {
-        let parent = weak.upgrade().expect("dangling weak pointer");
-        target.parent.set(Some(weak));
-        let i = match parent
-            .children
-            .borrow()
-            .iter()
-            .enumerate()
-            .find(|&(_, child)| Rc::ptr_eq(child, target))
-        {
-            Some((i, _)) => i,
-            None => panic!("have parent but couldn't find in parent's children!"),
-        };
-        Some((parent, i))
-    } else {
-        None
+/// A plain owned copy of a tree, without reference counting or parent pointers
+///
+/// Unlike [`Handle`], it is `Send + Sync`, so it can be cached or moved across threads. Convert
+/// with [`to_owned_tree`] and back with [`from_owned_tree`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OwnedNode {
+    /// Tag name of an element, `None` for texts and documents
+    pub tag: Option<String>,
+    /// Attributes of an element, as `(name, value)` pairs in source order
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<OwnedNode>,
+    /// Content of a text, `None` for elements and documents
+    pub text: Option<String>,
+}
+
+/// Copy a tree into an [`OwnedNode`]
+///
+/// Only elements, texts and documents are kept; comments, doctypes and processing instructions
+/// are dropped. Element and attribute namespaces are not kept either.
+pub fn to_owned_tree(node: &Handle) -> OwnedNode {
+    let mut owned = OwnedNode::default();
+    match &node.data {
+        NodeData::Element { name, attrs, .. } => {
+            owned.tag = Some(name.local.to_string());
+            owned.attrs = attrs
+                .borrow()
+                .iter()
+                .map(|attr| (attr.name.local.to_string(), attr.value.to_string()))
+                .collect();
+        }
+        NodeData::Text { text } => owned.text = Some(text.borrow().to_string()),
+        _ => {}
     }
+    owned.children = node
+        .children
+        .borrow()
+        .iter()
+        .filter(|child| {
+            matches!(
+                child.data,
+                NodeData::Element { .. } | NodeData::Text { .. } | NodeData::Document
+            )
+        })
+        .map(to_owned_tree)
+        .collect();
+    owned
+}
+
+/// Rebuild a tree from an [`OwnedNode`], with parent pointers set
+///
+/// Elements are created in the HTML namespace, and a node with neither a tag nor a text
+/// becomes a `Document`.
+pub fn from_owned_tree(owned: &OwnedNode) -> Handle {
+    let node = match (&owned.tag, &owned.text) {
+        (Some(tag), _) => {
+            let attrs: Vec<(&str, &str)> = owned
+                .attrs
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect();
+            Node::new_element(tag, &attrs)
+        }
+        (None, Some(text)) => Node::new_text(text.clone()),
+        (None, None) => Node::new(NodeData::Document),
+    };
+    for child in &owned.children {
+        append(&node, from_owned_tree(child));
+    }
+    node
+}
+
+/// Keep the nodes that are not descendants of another one of the nodes, in the order given
+///
+/// Duplicates are only kept once.
+pub(crate) fn outermost(nodes: Vec<Handle>) -> Vec<Handle> {
+    let included = nodes.iter().map(Rc::as_ptr).collect::<HashSet<_>>();
+    let mut seen = HashSet::new();
+
+    nodes
+        .into_iter()
+        .filter(|node| {
+            if !seen.insert(Rc::as_ptr(node)) {
+                return false;
+            }
+            let mut current = node.get_parent();
+            while let Some(parent) = current {
+                if included.contains(&Rc::as_ptr(&parent)) {
+                    return false;
+                }
+                current = parent.get_parent();
+            }
+            true
+        })
+        .collect()
+}
+
+/// Compare the position of two nodes in document order
+///
+/// An ancestor comes before its descendants, and siblings and their subtrees are ordered by
+/// their index at the lowest common ancestor. Returns `None` if the nodes are not in the same
+/// tree, or if the tree is inconsistent (a parent does not list its child).
+pub fn document_order(a: &Handle, b: &Handle) -> Option<Ordering> {
+    // The ancestor chains, from the root down to the node
+    let chain = |node: &Handle| {
+        let mut chain = vec![node.clone()];
+        while let Some(parent) = chain.last().and_then(|node| node.get_parent()) {
+            chain.push(parent);
+        }
+        chain.reverse();
+        chain
+    };
+    let (chain_a, chain_b) = (chain(a), chain(b));
+    if !Rc::ptr_eq(&chain_a[0], &chain_b[0]) {
+        return None;
+    }
+
+    // The first nodes below the lowest common ancestor
+    let common = chain_a
+        .iter()
+        .zip(&chain_b)
+        .take_while(|(a, b)| Rc::ptr_eq(a, b))
+        .count();
+    match (chain_a.get(common), chain_b.get(common)) {
+        (None, None) => Some(Ordering::Equal),
+        (None, Some(_)) => Some(Ordering::Less),
+        (Some(_), None) => Some(Ordering::Greater),
+        (Some(a), Some(b)) => {
+            let children = chain_a[common - 1].children.borrow();
+            let index_a = children.iter().position(|child| Rc::ptr_eq(child, a))?;
+            let index_b = children.iter().position(|child| Rc::ptr_eq(child, b))?;
+            Some(index_a.cmp(&index_b))
+        }
+    }
+}
+
+/// Get the parent of a node and the node's index among the parent's children
+///
+/// Returns `None` instead of panicking if the tree is inconsistent:
+/// - the node has no parent,
+/// - the parent has been dropped (dangling weak pointer),
+/// - or the parent does not list the node among its children.
+pub fn parent_and_index(node: &Handle) -> Option<(Handle, usize)> {
+    let parent = node.get_parent()?;
+    let index = parent
+        .children
+        .borrow()
+        .iter()
+        .position(|child| Rc::ptr_eq(child, node))?;
+    Some((parent, index))
 }
 
 fn append_to_existing_text(prev: &Handle, text: &str) -> bool {
@@ -296,13 +619,220 @@ fn append_to_existing_text(prev: &Handle, text: &str) -> bool {
     }
 }
 
-fn remove_from_parent(target: &Handle) {
-    if let Some((parent, i)) = get_parent_and_index(target) {
+pub(crate) fn remove_from_parent(target: &Handle) {
+    if let Some((parent, i)) = parent_and_index(target) {
         parent.children.borrow_mut().remove(i);
         target.parent.set(None);
     }
 }
 
+/// Remove every attribute whose name satisfies `pred` from all elements in the tree
+///
+/// Returns the total number of removed attributes.
+pub fn remove_attributes<F: Fn(&str) -> bool>(tree: &Handle, pred: F) -> usize {
+    let mut removed = 0;
+    let mut stack = vec![tree.clone()];
+    while let Some(node) = stack.pop() {
+        if let NodeData::Element { ref attrs, .. } = node.data {
+            let mut attrs = attrs.borrow_mut();
+            let before = attrs.len();
+            attrs.retain(|attr| !pred(attr.name.local.as_ref()));
+            removed += before - attrs.len();
+        }
+        stack.extend(node.children.borrow().iter().cloned());
+    }
+    removed
+}
+
+/// Remove every node below `tree` for which `keep` returns `false`, hoisting its children
+///
+/// The tree is walked bottom-up, so `keep` sees each node after its own descendants have been
+/// filtered. The children of a removed node take its place in the parent, which flattens
+/// e.g. nested wrappers. The `tree` node itself is never removed. Adjacent text nodes are not
+/// merged after hoisting.
+pub fn retain<F: Fn(&Handle) -> bool>(tree: &Handle, keep: F) {
+    retain_children(tree, &keep);
+}
+
+fn retain_children<F: Fn(&Handle) -> bool>(node: &Handle, keep: &F) {
+    let children = mem::take(&mut *node.children.borrow_mut());
+    let mut new_children = Vec::with_capacity(children.len());
+    for child in children {
+        retain_children(&child, keep);
+        if keep(&child) {
+            new_children.push(child);
+        } else {
+            child.parent.set(None);
+            new_children.extend(mem::take(&mut *child.children.borrow_mut()));
+        }
+    }
+
+    for child in new_children.iter() {
+        child.parent.set(Some(Rc::downgrade(node)));
+    }
+    *node.children.borrow_mut() = new_children;
+}
+
+/// Write the text of every descendant text node of `tree`, in document order, to `w`
+///
+/// Text inside foreign content (inline SVG and MathML) is included; the parser stores it, and
+/// any `<![CDATA[...]]>` sections there, as ordinary text nodes.
+///
+/// Text is written as-is, straight from each node's buffer, without building an intermediate
+/// `String`. Use this to stream the text of large documents to a file or a tokenizer.
+pub fn write_text_content<W: io::Write>(tree: &Handle, w: &mut W) -> io::Result<()> {
+    for child in tree.children.borrow().iter() {
+        match &child.data {
+            NodeData::Text { text } => w.write_all(text.borrow().as_bytes())?,
+            _ => write_text_content(child, w)?,
+        }
+    }
+    Ok(())
+}
+
+/// Concatenate the text of every descendant text node of `tree` into a `String`
+///
+/// See [`write_text_content`] to stream the text instead.
+pub fn text_content(tree: &Handle) -> String {
+    let mut output = Vec::new();
+    write_text_content(tree, &mut output).expect("writing to a Vec never fails");
+    String::from_utf8(output).expect("text nodes are valid UTF-8")
+}
+
+/// Elements whose text is on its own lines, apart from the text around them
+pub(crate) const BLOCK_TAGS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "dd", "div", "dl", "dt", "figcaption", "figure",
+    "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header", "li", "main", "ol", "p", "pre",
+    "section", "table", "tr", "ul",
+];
+
+/// Tags whose text keeps its line breaks in [`normalized_text`] by default
+pub const DEFAULT_PRESERVE_TAGS: &[&str] = &["pre", "textarea"];
+
+/// Get the text of the tree with whitespace collapsed the way a browser renders it
+///
+/// Runs of whitespace become a single space and the result is trimmed, except for text inside
+/// an element whose tag is in `preserve_tags` (e.g. [`DEFAULT_PRESERVE_TAGS`], or `address` for
+/// postal addresses and verse), which is kept as is with its line breaks. `<br>` and the
+/// boundaries of block elements like `<p>` are written as line breaks, so the words of
+/// neighbouring blocks are never joined. Soft hyphens are removed and `<wbr>` adds nothing,
+/// so words broken up by either are joined back together.
+pub fn normalized_text(tree: &Handle, preserve_tags: &[&str]) -> String {
+    let mut output = String::new();
+    collect_normalized_text(tree, preserve_tags, false, &mut output);
+    output.trim().to_string()
+}
+
+fn collect_normalized_text(
+    node: &Handle,
+    preserve_tags: &[&str],
+    preserve: bool,
+    output: &mut String,
+) {
+    for child in node.children.borrow().iter() {
+        match &child.data {
+            NodeData::Text { text } if preserve => {
+                output.extend(text.borrow().chars().filter(|&c| c != '\u{00AD}'))
+            }
+            NodeData::Text { text } => {
+                let text = text.borrow().replace('\u{00AD}', "");
+                for (i, word) in text.split(char::is_whitespace).enumerate() {
+                    if i > 0 && !output.is_empty() && !output.ends_with(char::is_whitespace) {
+                        output.push(' ');
+                    }
+                    output.push_str(word);
+                }
+            }
+            NodeData::Element { name, .. } if name.local.as_ref() == "br" => {
+                // Replace a collapsed space before the break
+                if output.ends_with(' ') {
+                    output.pop();
+                }
+                output.push('\n');
+            }
+            NodeData::Element { name, .. } => {
+                let tag = name.local.as_ref();
+                let is_block = BLOCK_TAGS.contains(&tag);
+                if is_block {
+                    end_line(output);
+                }
+                let preserve = preserve || preserve_tags.contains(&tag);
+                collect_normalized_text(child, preserve_tags, preserve, output);
+                if is_block {
+                    end_line(output);
+                }
+            }
+            _ => collect_normalized_text(child, preserve_tags, preserve, output),
+        }
+    }
+}
+
+/// Start a new line of [`normalized_text`], unless the output is empty or already at one
+fn end_line(output: &mut String) {
+    if output.ends_with(' ') {
+        output.pop();
+    }
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+}
+
+/// Count the elements of the tree (including `tree` itself) by local name
+pub fn tag_histogram(tree: &Handle) -> HashMap<String, usize> {
+    let mut histogram = HashMap::new();
+    count_tags(tree, &mut histogram);
+    histogram
+}
+
+fn count_tags(node: &Handle, histogram: &mut HashMap<String, usize>) {
+    if let NodeData::Element { name, .. } = &node.data {
+        *histogram.entry(name.local.to_string()).or_insert(0) += 1;
+    }
+    for child in node.children.borrow().iter() {
+        count_tags(child, histogram);
+    }
+}
+
+/// Tags whose contents are never rendered
+const NON_RENDERED_TAGS: &[&str] = &["template", "script", "style"];
+
+/// Concatenate the text a user would likely see, skipping hidden subtrees
+///
+/// This can't evaluate CSS, so it approximates visibility from the markup alone. A subtree is
+/// skipped if its root:
+/// - has the `hidden` attribute,
+/// - has `aria-hidden="true"`,
+/// - or is a `<template>`, `<script>` or `<style>` element.
+///
+/// Elements hidden by stylesheets or inline `style="display: none"` are still included.
+pub fn visible_text(tree: &Handle) -> String {
+    let mut output = String::new();
+    collect_visible_text(tree, &mut output);
+    output
+}
+
+fn collect_visible_text(node: &Handle, output: &mut String) {
+    for child in node.children.borrow().iter() {
+        match &child.data {
+            NodeData::Text { text } => output.push_str(&text.borrow()),
+            NodeData::Element { name, attrs, .. } => {
+                if NON_RENDERED_TAGS.contains(&name.local.as_ref()) {
+                    continue;
+                }
+                let hidden = attrs.borrow().iter().any(|attr| match attr.name.local.as_ref() {
+                    "hidden" => true,
+                    "aria-hidden" => attr.value.trim().eq_ignore_ascii_case("true"),
+                    _ => false,
+                });
+                if !hidden {
+                    collect_visible_text(child, output);
+                }
+            }
+            _ => collect_visible_text(child, output),
+        }
+    }
+}
+
 /// The DOM itself; the result of parsing.
 pub struct Dom {
     /// The `Document` itself.
@@ -313,6 +843,173 @@ pub struct Dom {
 
     /// The document's quirks mode.
     pub quirks_mode: Cell<QuirksMode>,
+
+    /// Whether text appended next to a text node is merged into it
+    merge_text: bool,
+
+    /// Whether whitespace-only text that can't affect rendering is discarded
+    trim_whitespace: bool,
+}
+
+impl Dom {
+    /// Choose whether adjacent text is merged while parsing into this DOM
+    ///
+    /// By default the parser appends text to the previous text node when there is one, so
+    /// each run of text is a single node. When merging is disabled, every chunk of text the
+    /// tokenizer emits becomes its own node (e.g. text before and after a character reference
+    /// like `&amp;`), which keeps the boundaries needed to map text back to source positions.
+    /// The tradeoff is more nodes, and code that expects one text node per run of text (like
+    /// the `Text` match in a tree walk) has to handle several.
+    pub fn with_text_merging(mut self, merge: bool) -> Dom {
+        self.merge_text = merge;
+        self
+    }
+
+    /// Choose whether insignificant whitespace-only text is discarded while parsing into this
+    /// DOM
+    ///
+    /// Pretty-printed HTML is full of whitespace-only text between elements. When trimming is
+    /// enabled, whitespace-only text is dropped if it is the first child of a block element
+    /// (like `<div>`, `<ul>` or `<tr>`) or follows a block element, where a browser doesn't
+    /// render it either. Whitespace after text or inline elements is kept, since it separates
+    /// words, as is anything inside `<pre>`, `<textarea>`, `<listing>` and `<plaintext>`.
+    ///
+    /// Off by default, so that the tree matches the HTML specification.
+    pub fn with_whitespace_trimming(mut self, trim: bool) -> Dom {
+        self.trim_whitespace = trim;
+        self
+    }
+
+    /// Whether whitespace-only text appended to `parent` is insignificant, see
+    /// [`Dom::with_whitespace_trimming`]
+    fn is_insignificant_whitespace(&self, parent: &Handle, text: &str) -> bool {
+        if !self.trim_whitespace || !text.chars().all(|c| c.is_ascii_whitespace()) {
+            return false;
+        }
+        if !is_html_element_in(parent, BLOCK_CONTEXT_TAGS) {
+            return false;
+        }
+        let after_block = match parent.children.borrow().last() {
+            Some(last) => {
+                is_html_element_in(last, BLOCK_CONTEXT_TAGS)
+                    || is_html_element_in(last, PREFORMATTED_TAGS)
+            }
+            None => true,
+        };
+        if !after_block {
+            return false;
+        }
+
+        let mut current = Some(parent.clone());
+        while let Some(node) = current {
+            if is_html_element_in(&node, PREFORMATTED_TAGS) {
+                return false;
+            }
+            current = node.get_parent();
+        }
+        true
+    }
+
+    /// Get the `<body>` element of the document, if it has one
+    pub fn body(&self) -> Option<Handle> {
+        let html = find_html_child(&self.tree, "html")?;
+        find_html_child(&html, "body")
+    }
+
+    /// Select the nodes matching `selector` inside `<body>`
+    ///
+    /// Elements in `<head>` such as `<title>` and `<meta>` are never matched. Documents
+    /// without a `<body>` (e.g. a tree built by hand from fragments) are searched from the
+    /// document root instead. The document's quirks mode is taken into account.
+    pub fn select(&self, selector: &str) -> Vec<Handle> {
+        let root = self.body().unwrap_or_else(|| self.tree.clone());
+        select_with_options(&root, selector, &SelectorOptions::for_dom(self))
+    }
+
+    /// Dump the whole tree with the detailed `Debug` output of [`Node`]
+    ///
+    /// The `Debug` output of `Dom` itself is a one-line summary.
+    pub fn dump_tree(&self) -> String {
+        format!("{:#?}", self.tree)
+    }
+
+    /// Compute size metrics of the document in a single walk
+    pub fn stats(&self) -> DomStats {
+        let mut stats = DomStats::default();
+        let mut stack = vec![(self.tree.clone(), 0)];
+        while let Some((node, depth)) = stack.pop() {
+            stats.node_count += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+            match &node.data {
+                NodeData::Element { .. } => stats.element_count += 1,
+                NodeData::Text { text } => stats.text_bytes += text.borrow().len(),
+                _ => {}
+            }
+            for child in node.children.borrow().iter() {
+                stack.push((child.clone(), depth + 1));
+            }
+        }
+        stats
+    }
+}
+
+impl fmt::Debug for Dom {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let stats = self.stats();
+        fmt.debug_struct("Dom")
+            .field("nodes", &stats.node_count)
+            .field("elements", &stats.element_count)
+            .field("quirks_mode", &self.quirks_mode.get())
+            .field("errors", &self.errors.borrow().len())
+            .finish()
+    }
+}
+
+/// Size metrics of a document, see [`Dom::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DomStats {
+    /// Number of nodes of any kind, including the document node
+    pub node_count: usize,
+    /// Number of element nodes
+    pub element_count: usize,
+    /// Total length of all text nodes, in bytes
+    pub text_bytes: usize,
+    /// Depth of the deepest node; the document node has depth 0
+    pub max_depth: usize,
+}
+
+/// Elements whose leading whitespace and whitespace between child blocks is not rendered
+const BLOCK_CONTEXT_TAGS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "body", "caption", "colgroup", "dd", "details",
+    "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2", "h3",
+    "h4", "h5", "h6", "head", "header", "hr", "html", "li", "main", "menu", "nav", "ol", "p",
+    "section", "select", "summary", "table", "tbody", "td", "tfoot", "th", "thead", "tr", "ul",
+];
+
+/// Elements that can't have content and are written without a close tag, the same as
+/// html5ever's serializer
+pub const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+/// Elements whose whitespace is rendered as is
+const PREFORMATTED_TAGS: &[&str] = &["pre", "textarea", "listing", "plaintext"];
+
+/// Check whether the node is an HTML element with one of the given names
+fn is_html_element_in(node: &Handle, tags: &[&str]) -> bool {
+    matches!(&node.data, NodeData::Element { name, .. } if name.ns == ns!(html) && tags.contains(&name.local.as_ref()))
+}
+
+/// Find the first child of `node` that is an HTML element named `tag`
+fn find_html_child(node: &Handle, tag: &str) -> Option<Handle> {
+    node.children
+        .borrow()
+        .iter()
+        .find(|child| {
+            matches!(&child.data, NodeData::Element { name, .. } if name.ns == ns!(html) && name.local.as_ref() == tag)
+        })
+        .cloned()
 }
 
 impl TreeSink for Dom {
@@ -388,8 +1085,15 @@ impl TreeSink for Dom {
     }
 
     fn append(&self, parent: &Handle, child: NodeOrText<Handle>) {
-        // Append to an existing Text node if we have one.
         if let NodeOrText::AppendText(text) = &child
+            && self.is_insignificant_whitespace(parent, text)
+        {
+            return;
+        }
+
+        // Append to an existing Text node if we have one.
+        if self.merge_text
+            && let NodeOrText::AppendText(text) = &child
             && let Some(h) = parent.children.borrow().last()
             && append_to_existing_text(h, text)
         {
@@ -408,7 +1112,7 @@ impl TreeSink for Dom {
     }
 
     fn append_before_sibling(&self, sibling: &Handle, child: NodeOrText<Handle>) {
-        let (parent, i) = get_parent_and_index(sibling)
+        let (parent, i) = parent_and_index(sibling)
             .expect("append_before_sibling called on node without parent");
 
         let child = match (child, i) {
@@ -421,7 +1125,7 @@ impl TreeSink for Dom {
             (NodeOrText::AppendText(text), i) => {
                 let children = parent.children.borrow();
                 let prev = &children[i - 1];
-                if append_to_existing_text(prev, &text) {
+                if self.merge_text && append_to_existing_text(prev, &text) {
                     return;
                 }
                 Node::new(NodeData::Text {
@@ -482,15 +1186,17 @@ impl TreeSink for Dom {
             panic!("not an element")
         };
 
-        let existing_names = existing
+        // The set is only used for membership; new attributes are appended in the order
+        // they are given so that serialization stays deterministic
+        let mut existing_names = existing
             .iter()
             .map(|e| e.name.clone())
             .collect::<HashSet<_>>();
-        existing.extend(
-            attrs
-                .into_iter()
-                .filter(|attr| !existing_names.contains(&attr.name)),
-        );
+        for attr in attrs {
+            if existing_names.insert(attr.name.clone()) {
+                existing.push(attr);
+            }
+        }
     }
 
     fn remove_from_parent(&self, target: &Handle) {
@@ -517,13 +1223,21 @@ impl Default for Dom {
             tree: Node::new(NodeData::Document),
             errors: Default::default(),
             quirks_mode: Cell::new(QuirksMode::NoQuirks),
+            merge_text: true,
+            trim_whitespace: false,
         }
     }
 }
 
 enum SerializeOp {
     Open(Handle),
-    Close(QualName),
+    Close(Handle, QualName),
+}
+
+/// A step of [`serialize_nodes`] around the markup of a node
+enum Visit {
+    Enter,
+    Leave,
 }
 
 pub struct SerializableHandle(Handle);
@@ -539,67 +1253,953 @@ impl Serialize for SerializableHandle {
     where
         S: Serializer,
     {
-        let mut ops = VecDeque::new();
-        match traversal_scope {
-            TraversalScope::IncludeNode => ops.push_back(SerializeOp::Open(self.0.clone())),
-            TraversalScope::ChildrenOnly(_) => ops.extend(
-                self.0
-                    .children
-                    .borrow()
-                    .iter()
-                    .map(|h| SerializeOp::Open(h.clone())),
-            ),
-        }
-
-        while let Some(op) = ops.pop_front() {
-            match op {
-                SerializeOp::Open(handle) => match handle.data {
-                    NodeData::Element {
-                        ref name,
-                        ref attrs,
-                        ..
-                    } => {
-                        serializer.start_elem(
-                            name.clone(),
-                            attrs.borrow().iter().map(|at| (&at.name, &at.value[..])),
-                        )?;
-
-                        ops.reserve(1 + handle.children.borrow().len());
-                        ops.push_front(SerializeOp::Close(name.clone()));
-
-                        for child in handle.children.borrow().iter().rev() {
-                            ops.push_front(SerializeOp::Open(child.clone()));
-                        }
+        serialize_nodes(&self.0, serializer, traversal_scope, |_, _| {})
+    }
+}
+
+/// Write the tree to the serializer, calling `visit` right before and right after the markup
+/// of each node
+///
+/// A document has no markup of its own and is not visited.
+fn serialize_nodes<S, F>(
+    root: &Handle,
+    serializer: &mut S,
+    traversal_scope: TraversalScope,
+    mut visit: F,
+) -> io::Result<()>
+where
+    S: Serializer,
+    F: FnMut(&Handle, Visit),
+{
+    let mut ops = VecDeque::new();
+    match traversal_scope {
+        TraversalScope::IncludeNode => ops.push_back(SerializeOp::Open(root.clone())),
+        TraversalScope::ChildrenOnly(_) => ops.extend(
+            root.children
+                .borrow()
+                .iter()
+                .map(|h| SerializeOp::Open(h.clone())),
+        ),
+    }
+
+    while let Some(op) = ops.pop_front() {
+        match op {
+            SerializeOp::Open(handle) => match handle.data {
+                NodeData::Element {
+                    ref name,
+                    ref attrs,
+                    ref template_contents,
+                    ..
+                } => {
+                    visit(&handle, Visit::Enter);
+                    // Values are passed raw; the serializer escapes `&`, `"` and NBSP in
+                    // attribute values, and `&`, `<`, `>` and NBSP in text
+                    serializer.start_elem(
+                        name.clone(),
+                        attrs.borrow().iter().map(|at| (&at.name, &at.value[..])),
+                    )?;
+
+                    // The parser drops a newline right after these start tags, so a text
+                    // starting with a newline needs an extra one to survive a round trip
+                    if name.ns == ns!(html)
+                        && matches!(name.local.as_ref(), "pre" | "textarea" | "listing")
+                        && let Some(first) = handle.children.borrow().first()
+                        && let NodeData::Text { ref text } = first.data
+                        && text.borrow().starts_with('\n')
+                    {
+                        serializer.write_text("\n")?;
                     }
 
-                    NodeData::Doctype { ref name, .. } => serializer.write_doctype(name)?,
+                    // The content of a template lives in its contents fragment
+                    let content = match &*template_contents.borrow() {
+                        Some(contents) => contents.clone(),
+                        None => handle.clone(),
+                    };
 
-                    NodeData::Text { ref text } => serializer.write_text(&text.borrow())?,
+                    ops.reserve(1 + content.children.borrow().len());
+                    // The serializer writes nothing for the close of a void element, but
+                    // still needs it to keep track of the open elements
+                    ops.push_front(SerializeOp::Close(handle.clone(), name.clone()));
 
-                    NodeData::Comment { ref comment } => serializer.write_comment(comment)?,
+                    // A void element can't hold children, so any set on it are dropped
+                    if is_html_element_in(&handle, VOID_ELEMENTS) {
+                        continue;
+                    }
 
-                    NodeData::ProcessingInstruction {
-                        ref target,
-                        ref data,
-                    } => serializer.write_processing_instruction(target, data)?,
+                    for child in content.children.borrow().iter().rev() {
+                        ops.push_front(SerializeOp::Open(child.clone()));
+                    }
+                }
 
-                    NodeData::Document => panic!("Can't serialize Document node itself"),
-                },
+                NodeData::Doctype { ref name, .. } => {
+                    visit(&handle, Visit::Enter);
+                    serializer.write_doctype(name)?;
+                    visit(&handle, Visit::Leave);
+                }
+
+                NodeData::Text { ref text } => {
+                    visit(&handle, Visit::Enter);
+                    serializer.write_text(&text.borrow())?;
+                    visit(&handle, Visit::Leave);
+                }
 
-                SerializeOp::Close(name) => {
-                    serializer.end_elem(name)?;
+                NodeData::Comment { ref comment } => {
+                    visit(&handle, Visit::Enter);
+                    serializer.write_comment(comment)?;
+                    visit(&handle, Visit::Leave);
                 }
+
+                NodeData::ProcessingInstruction {
+                    ref target,
+                    ref data,
+                } => {
+                    visit(&handle, Visit::Enter);
+                    serializer.write_processing_instruction(target, data)?;
+                    visit(&handle, Visit::Leave);
+                }
+
+                // A document has no markup of its own; serialize its children in place
+                NodeData::Document => {
+                    for child in handle.children.borrow().iter().rev() {
+                        ops.push_front(SerializeOp::Open(child.clone()));
+                    }
+                }
+            },
+
+            SerializeOp::Close(handle, name) => {
+                serializer.end_elem(name)?;
+                visit(&handle, Visit::Leave);
             }
         }
+    }
+
+    Ok(())
+}
+
+/// A writer that keeps count of the bytes written, readable while the serializer owns it
+struct CountingWriter<W> {
+    inner: W,
+    written: Rc<Cell<usize>>,
+}
+
+impl<W: io::Write> io::Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written.set(self.written.get() + n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Byte ranges of serialized HTML, each with the node written there
+pub type NodeRanges = Vec<(Range<usize>, Handle)>;
+
+/// Serializes a node like [`SerializableHandle`], recording where each node was written
+struct RangeRecorder {
+    node: Handle,
+    written: Rc<Cell<usize>>,
+    ranges: RefCell<NodeRanges>,
+}
 
-        Ok(())
+impl Serialize for RangeRecorder {
+    fn serialize<S>(&self, serializer: &mut S, traversal_scope: TraversalScope) -> io::Result<()>
+    where
+        S: Serializer,
+    {
+        // Ranges are pushed when a node is entered, so they stay in document order
+        let mut ranges = self.ranges.borrow_mut();
+        let mut open = Vec::new();
+        serialize_nodes(&self.node, serializer, traversal_scope, |handle, visit| {
+            let offset = self.written.get();
+            match visit {
+                Visit::Enter => {
+                    open.push(ranges.len());
+                    ranges.push((offset..offset, handle.clone()));
+                }
+                Visit::Leave => {
+                    if let Some(index) = open.pop() {
+                        ranges[index].0.end = offset;
+                    }
+                }
+            }
+        })
     }
 }
 
-pub fn serialize_to_string(node: &Handle) -> String {
+/// Serialize the children of a node into HTML, along with the byte range of the output each
+/// descendant was written to
+///
+/// The ranges are in document order and cover the whole markup of a node, from its start tag
+/// to its end tag; the range of a text covers the text as escaped in the output. Errors are
+/// reported like [`serialize_to_string`].
+pub fn serialize_with_ranges(node: &Handle) -> io::Result<(String, NodeRanges)> {
+    let written = Rc::new(Cell::new(0));
+    let recorder = RangeRecorder {
+        node: node.clone(),
+        written: written.clone(),
+        ranges: RefCell::new(Vec::new()),
+    };
+    let mut writer = CountingWriter {
+        inner: Vec::new(),
+        written,
+    };
+    let serialize_opts = SerializeOpts {
+        traversal_scope: TraversalScope::ChildrenOnly(None),
+        ..Default::default()
+    };
+    serialize(&mut writer, &recorder, serialize_opts)?;
+    Ok((utf8_output(writer.inner)?, recorder.ranges.into_inner()))
+}
+
+/// Convert the output of the serializer to a string, reporting invalid UTF-8 as an error of
+/// kind [`io::ErrorKind::InvalidData`]
+fn utf8_output(output: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(output).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Serialize the children of a node into HTML
+///
+/// Serializer errors are returned as is, and output that is not valid UTF-8 as an error of
+/// kind [`io::ErrorKind::InvalidData`]. See [`serialize_to_string_lossy`] for an infallible
+/// version.
+pub fn serialize_to_string(node: &Handle) -> io::Result<String> {
+    let mut output = Vec::new();
+    let serialize_opts = SerializeOpts {
+        traversal_scope: TraversalScope::ChildrenOnly(None),
+        ..Default::default()
+    };
+    serialize(&mut output, &SerializableHandle::from(node.clone()), serialize_opts)?;
+    utf8_output(output)
+}
+
+/// Serialize the children of a node into HTML, never failing
+///
+/// Invalid UTF-8 is replaced with U+FFFD, and if the serializer fails, the HTML written up to
+/// that point is returned.
+pub fn serialize_to_string_lossy(node: &Handle) -> String {
+    let mut output = Vec::new();
+    let serialize_opts = SerializeOpts {
+        traversal_scope: TraversalScope::ChildrenOnly(None),
+        ..Default::default()
+    };
+    let _ = serialize(&mut output, &SerializableHandle::from(node.clone()), serialize_opts);
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// Serialize a node into HTML with custom html5ever options
+///
+/// `scope` replaces the `traversal_scope` of `opts`:
+/// - [`TraversalScope::IncludeNode`] writes the node with its own tags (outer HTML),
+/// - [`TraversalScope::ChildrenOnly`] writes only its children (inner HTML).
+///
+/// Of `opts`, `scripting_enabled` is the one most likely to change: it is `true` by default,
+/// which writes the contents of `<noscript>` as raw text, while `false` escapes them like
+/// regular text.
+///
+/// Errors are reported like [`serialize_to_string`].
+pub fn serialize_with_opts(
+    node: &Handle,
+    opts: SerializeOpts,
+    scope: TraversalScope,
+) -> io::Result<String> {
     let mut output = Vec::new();
-    let serialize_opts = SerializeOpts::default();
+    let serialize_opts = SerializeOpts {
+        traversal_scope: scope,
+        ..opts
+    };
     let serializable = SerializableHandle::from(node.clone());
-    serialize(&mut output, &serializable, serialize_opts).unwrap();
-    String::from_utf8(output).unwrap()
+    serialize(&mut output, &serializable, serialize_opts)?;
+    utf8_output(output)
+}
+
+/// Serialize the outermost of the given nodes as one HTML fragment
+///
+/// Each node is written with its own tags (outer HTML), in document order, so merged results
+/// of several queries come out as they appear in the page. Nodes of separate trees are
+/// grouped by tree, in the order each tree first appears in `matches`. A node that is a
+/// descendant of another given node, or that appears twice, is written only once as part of
+/// its outermost match.
+///
+/// Errors are reported like [`serialize_to_string`].
+pub fn serialize_selection(matches: &[Handle]) -> io::Result<String> {
+    let mut roots = HashMap::new();
+    let mut nodes: Vec<(usize, Handle)> = outermost(matches.to_vec())
+        .into_iter()
+        .map(|node| {
+            let mut root = node.clone();
+            while let Some(parent) = root.get_parent() {
+                root = parent;
+            }
+            let next_rank = roots.len();
+            (*roots.entry(Rc::as_ptr(&root)).or_insert(next_rank), node)
+        })
+        .collect();
+    nodes.sort_by(|(a_rank, a), (b_rank, b)| {
+        a_rank
+            .cmp(b_rank)
+            .then_with(|| document_order(a, b).unwrap_or(Ordering::Equal))
+    });
+
+    let mut output = Vec::new();
+    for (_, node) in nodes.iter() {
+        let serialize_opts = SerializeOpts {
+            traversal_scope: TraversalScope::IncludeNode,
+            ..Default::default()
+        };
+        let serializable = SerializableHandle::from(node.clone());
+        serialize(&mut output, &serializable, serialize_opts)?;
+    }
+    utf8_output(output)
+}
+
+/// Serialize a whole parsed document, including its doctype, back into HTML
+///
+/// Errors are reported like [`serialize_to_string`].
+pub fn serialize_document(dom: &Dom) -> io::Result<String> {
+    let mut output = Vec::new();
+    let serialize_opts = SerializeOpts {
+        traversal_scope: TraversalScope::ChildrenOnly(None),
+        ..Default::default()
+    };
+    let serializable = SerializableHandle::from(dom.tree.clone());
+    serialize(&mut output, &serializable, serialize_opts)?;
+    utf8_output(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_html;
+    use crate::selector::select;
+
+    #[test]
+    fn test_serialize_document() {
+        let dom = parse_html("<!DOCTYPE html><title>Title</title><p>Text</p>");
+        assert_eq!(
+            serialize_document(&dom).unwrap(),
+            "<!DOCTYPE html><html><head><title>Title</title></head><body><p>Text</p></body></html>"
+        );
+
+        // Including the document node itself no longer panics
+        let mut output = Vec::new();
+        let opts = SerializeOpts {
+            traversal_scope: TraversalScope::IncludeNode,
+            ..Default::default()
+        };
+        serialize(&mut output, &SerializableHandle::from(dom.tree.clone()), opts).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            serialize_document(&dom).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_serialize_to_string_result() {
+        let dom = parse_html("<p>caf\u{e9} &amp; <b>cr\u{e8}me</b></p>");
+        let body = dom.body().unwrap();
+
+        let html = serialize_to_string(&body).unwrap();
+        assert_eq!(html, "<p>caf\u{e9} &amp; <b>cr\u{e8}me</b></p>");
+        assert_eq!(serialize_to_string_lossy(&body), html);
+    }
+
+    #[test]
+    fn test_owned_tree_round_trip() {
+        fn assert_send_sync<T: Send + Sync + Clone>(_: &T) {}
+
+        let dom = parse_html(
+            r#"<div id="main" class="a b"><p>Hello <b>world</b><!--gone--></p><img src="x.png"></div>"#,
+        );
+        let div = select(&dom.tree, "div")[0].clone();
+        let owned = to_owned_tree(&div);
+        assert_send_sync(&owned);
+
+        assert_eq!(owned.tag.as_deref(), Some("div"));
+        assert_eq!(
+            owned.attrs,
+            [("id".to_string(), "main".to_string()), ("class".to_string(), "a b".to_string())]
+        );
+        assert_eq!(owned.children[0].children.len(), 2);
+        assert_eq!(owned.children[0].children[0].text.as_deref(), Some("Hello "));
+
+        let rebuilt = from_owned_tree(&owned);
+        assert_eq!(
+            serialize_with_opts(&rebuilt, SerializeOpts::default(), TraversalScope::IncludeNode)
+                .unwrap(),
+            r#"<div id="main" class="a b"><p>Hello <b>world</b></p><img src="x.png"></div>"#
+        );
+        let b = select(&rebuilt, "b")[0].clone();
+        let p = b.get_parent().unwrap();
+        assert!(Rc::ptr_eq(&p.get_parent().unwrap(), &rebuilt));
+        assert_eq!(to_owned_tree(&rebuilt), owned);
+
+        // A whole document round trips too
+        let owned = to_owned_tree(&dom.tree);
+        assert_eq!(owned.tag, None);
+        assert_eq!(to_owned_tree(&from_owned_tree(&owned)), owned);
+    }
+
+    #[test]
+    fn test_serialize_with_ranges() {
+        let dom = parse_html("<p>Fish &amp; chips<!--x--></p><ul><li>One</li></ul>");
+        let body = dom.body().unwrap();
+        let (html, ranges) = serialize_with_ranges(&body).unwrap();
+        assert_eq!(html, serialize_to_string(&body).unwrap());
+
+        let slices: Vec<&str> = ranges.iter().map(|(range, _)| &html[range.clone()]).collect();
+        assert_eq!(
+            slices,
+            [
+                "<p>Fish &amp; chips<!--x--></p>",
+                "Fish &amp; chips",
+                "<!--x-->",
+                "<ul><li>One</li></ul>",
+                "<li>One</li>",
+                "One",
+            ]
+        );
+        assert!(Rc::ptr_eq(&ranges[4].1, &select(&body, "li")[0]));
+    }
+
+    #[test]
+    fn test_serialize_void_elements() {
+        let dom = parse_html(r#"<p>A <img src="cat.png" alt="Cat"> and<br>a line</p>"#);
+        let p = select(&dom.tree, "p")[0].clone();
+        assert_eq!(
+            serialize_to_string(&p).unwrap(),
+            r#"A <img src="cat.png" alt="Cat"> and<br>a line"#
+        );
+
+        // Children set on a void element can't be written, and don't leave a close tag
+        let img = select(&p, "img")[0].clone();
+        img.children.borrow_mut().push(Node::new_text("stray".to_string()));
+        assert_eq!(
+            serialize_to_string(&p).unwrap(),
+            r#"A <img src="cat.png" alt="Cat"> and<br>a line"#
+        );
+
+        // Including the legacy void elements, like a hand-built <param>
+        let object = Node::new_element("object", &[]);
+        let param = Node::new_element("param", &[("name", "autoplay")]);
+        param.children.borrow_mut().push(Node::new_text("stray".to_string()));
+        object.children.borrow_mut().push(param);
+        object.children.borrow_mut().push(Node::new_text("Fallback".to_string()));
+        assert_eq!(
+            serialize_to_string(&object).unwrap(),
+            r#"<param name="autoplay">Fallback"#
+        );
+    }
+
+    #[test]
+    fn test_serialize_escaping_round_trip() {
+        let title = "a \"quoted\" & <tag> 'single'";
+        let text = "1 < 2 && \"x\" > 0";
+
+        // Build the tree by hand so that the values are not shaped by the parser
+        let dom = Dom::default();
+        let p = Node::new_element("p", &[("title", title), ("data-x", "&amp;")]);
+        append(&p, Node::new_text(text.to_string()));
+        append(&dom.tree, p);
+
+        let html = serialize_document(&dom).unwrap();
+        assert_eq!(
+            html,
+            "<p title=\"a &quot;quoted&quot; &amp; <tag> 'single'\" data-x=\"&amp;amp;\">\
+            1 &lt; 2 &amp;&amp; \"x\" &gt; 0</p>"
+        );
+
+        // Parsing the output gives back the same values, and serializing again is stable
+        let reparsed = parse_html(&html);
+        let p = select(&reparsed.tree, "p").remove(0);
+        let NodeData::Element { attrs, .. } = &p.data else {
+            panic!("not an element");
+        };
+        let attrs = attrs
+            .borrow()
+            .iter()
+            .map(|attr| (attr.name.local.to_string(), attr.value.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            attrs,
+            vec![
+                ("title".to_string(), title.to_string()),
+                ("data-x".to_string(), "&amp;".to_string()),
+            ]
+        );
+        assert_eq!(text_content(&p), text);
+
+        let body = reparsed.body().unwrap();
+        assert_eq!(serialize_to_string(&body).unwrap(), html);
+    }
+
+    #[test]
+    fn test_serialize_selection() {
+        let dom = parse_html(
+            "<div class=\"item\"><p class=\"item\">One</p></div><p>Skip</p><span class=\"item\">Two</span>",
+        );
+        let matches = select(&dom.tree, ".item");
+        assert_eq!(matches.len(), 3);
+        assert_eq!(
+            serialize_selection(&matches).unwrap(),
+            "<div class=\"item\"><p class=\"item\">One</p></div><span class=\"item\">Two</span>"
+        );
+
+        let span = matches[2].clone();
+        assert_eq!(
+            serialize_selection(&[span.clone(), span]).unwrap(),
+            "<span class=\"item\">Two</span>"
+        );
+        assert_eq!(serialize_selection(&[]).unwrap(), "");
+
+        // Merged selections are written in document order
+        let merged = [select(&dom.tree, "span"), select(&dom.tree, "p")].concat();
+        assert_eq!(
+            serialize_selection(&merged).unwrap(),
+            "<p class=\"item\">One</p><p>Skip</p><span class=\"item\">Two</span>"
+        );
+        let merged = [select(&dom.tree, "p.item"), select(&dom.tree, "div")].concat();
+        assert_eq!(
+            serialize_selection(&merged).unwrap(),
+            "<div class=\"item\"><p class=\"item\">One</p></div>"
+        );
+    }
+
+    /// Check that two trees have the same shape and equal nodes (by `NodeData`)
+    fn assert_same_tree(a: &Handle, b: &Handle, html: &str) {
+        assert_eq!(a.data, b.data, "{}", html);
+        let a_children = a.children.borrow();
+        let b_children = b.children.borrow();
+        assert_eq!(a_children.len(), b_children.len(), "{}", html);
+        for (a, b) in a_children.iter().zip(b_children.iter()) {
+            assert_same_tree(a, b, html);
+        }
+        if let (
+            NodeData::Element {
+                template_contents: a,
+                ..
+            },
+            NodeData::Element {
+                template_contents: b,
+                ..
+            },
+        ) = (&a.data, &b.data)
+            && let (Some(a), Some(b)) = (&*a.borrow(), &*b.borrow())
+        {
+            assert_same_tree(a, b, html);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let corpus = [
+            "<!DOCTYPE html><html lang=\"en\"><head><title>T</title></head><body></body></html>",
+            "<p>Text with <b>bold</b> and <i>italic</i> text.</p>",
+            "<div c=\"3\" a=\"1\" b=\"2\"><span id=x class=\"a b\">x</span></div>",
+            "<p title='a \"quoted\" & <tag>'>1 &lt; 2 &amp;&amp; 3 &gt; 2&nbsp;!</p>",
+            "<img src=\"a.png\" alt=\"\"><br><input type=checkbox checked><hr/>",
+            "<ul><li>One<li>Two</ul><table><tr><td>1<td>2</table>",
+            "<p>Unclosed <b>bold <i>both</b> italic</i></p>",
+            "<pre>\n\nindented\n  code</pre><textarea>\nvalue</textarea>",
+            "<script>if (a < b && c > d) {}</script><style>p > a { color: red }</style>",
+            "<!-- comment --><p>after</p><!---->",
+            "<template><p>Template</p></template>",
+            "<svg viewBox=\"0 0 1 1\"><circle r=\"1\"/><text>Label</text></svg>",
+            "<math><mi>x</mi></math>",
+            "<p>a</p>   \n  <p>b</p>",
+            "text only",
+            "",
+        ];
+
+        for html in corpus {
+            let dom = parse_html(html);
+            let serialized = serialize_document(&dom).unwrap();
+            let reparsed = parse_html(&serialized);
+            assert_same_tree(&dom.tree, &reparsed.tree, html);
+
+            // Serializing is stable after the first round trip
+            assert_eq!(serialize_document(&reparsed).unwrap(), serialized, "{}", html);
+        }
+    }
+
+    #[test]
+    fn test_set_text() {
+        let dom = parse_html("<p>Call 555-0100 now</p>");
+        let p = crate::selector::select(&dom.tree, "p").remove(0);
+        let text = p.children.borrow()[0].clone();
+
+        assert!(text.set_text("Call [redacted] now"));
+        assert!(!p.set_text("ignored"));
+        assert_eq!(serialize_to_string(&p).unwrap(), "Call [redacted] now");
+    }
+
+    #[test]
+    fn test_retain() {
+        let dom = parse_html("<div><div><p>a</p></div><p>b</p><div><div><p>c</p></div></div></div>");
+        retain(&dom.tree, |node| {
+            !matches!(&node.data, NodeData::Element { name, .. } if name.local.as_ref() == "div")
+        });
+
+        assert_eq!(
+            serialize_to_string(&dom.tree).unwrap(),
+            "<html><head></head><body><p>a</p><p>b</p><p>c</p></body></html>"
+        );
+
+        // Hoisted children point to their new parent
+        let body = select(&dom.tree, "body").remove(0);
+        for child in body.children.borrow().iter() {
+            assert!(Rc::ptr_eq(&child.get_parent().unwrap(), &body));
+        }
+    }
+
+    #[test]
+    fn test_add_attrs_if_missing_order() {
+        // A second <html> tag adds its missing attributes to the existing element
+        let html = "<html lang=\"en\" dir=\"ltr\"><body>\
+            <html data-b=\"2\" lang=\"fr\" data-a=\"1\" class=\"c\" data-b=\"3\"><p>Text</p>";
+        let expected = "<html lang=\"en\" dir=\"ltr\" data-b=\"2\" data-a=\"1\" class=\"c\">\
+            <head></head><body><p>Text</p></body></html>";
+        for _ in 0..10 {
+            assert_eq!(serialize_document(&parse_html(html)).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_dom_select() {
+        let dom = parse_html(
+            "<head><title>Title</title><meta name=\"a\"></head><body><p>One</p><p>Two</p></body>",
+        );
+        assert!(dom.select("title").is_empty());
+        assert!(dom.select("meta").is_empty());
+        assert_eq!(dom.select("p").len(), 2);
+        assert_eq!(dom.select("body").len(), 1);
+
+        // Without a <body>, the whole document is searched
+        let dom = Dom::default();
+        let div = Node::new_element("div", &[]);
+        append(&div, Node::new_element("p", &[]));
+        append(&dom.tree, div);
+        assert!(dom.body().is_none());
+        assert_eq!(dom.select("div p").len(), 1);
+    }
+
+    #[test]
+    fn test_text_merging() {
+        use html5ever::driver::ParseOpts;
+        use html5ever::parse_document;
+        use html5ever::tendril::TendrilSink;
+
+        let html = "<p>fish &amp; chips</p>";
+        let parse = |dom: Dom| parse_document(dom, ParseOpts::default()).one(html);
+        let text_nodes = |dom: &Dom| {
+            let p = select(&dom.tree, "p").remove(0);
+            p.children
+                .borrow()
+                .iter()
+                .map(|child| match &child.data {
+                    NodeData::Text { text } => text.borrow().to_string(),
+                    _ => panic!("expected only text"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let merged = parse(Dom::default());
+        assert_eq!(text_nodes(&merged), vec!["fish & chips"]);
+
+        let separate = parse(Dom::default().with_text_merging(false));
+        let texts = text_nodes(&separate);
+        assert!(texts.len() > 1);
+        assert_eq!(texts.concat(), "fish & chips");
+    }
+
+    #[test]
+    fn test_serialize_with_opts() {
+        let dom = parse_html("<div id=\"a\"><p>One</p><noscript></noscript></div>");
+        let div = select(&dom.tree, "div").remove(0);
+        let noscript = select(&div, "noscript").remove(0);
+        append(&noscript, Node::new_text("<b>".to_string()));
+
+        assert_eq!(
+            serialize_with_opts(&div, SerializeOpts::default(), TraversalScope::IncludeNode)
+                .unwrap(),
+            "<div id=\"a\"><p>One</p><noscript><b></noscript></div>"
+        );
+        assert_eq!(
+            serialize_with_opts(
+                &div,
+                SerializeOpts::default(),
+                TraversalScope::ChildrenOnly(None)
+            )
+            .unwrap(),
+            serialize_to_string(&div).unwrap()
+        );
+
+        let no_scripting = SerializeOpts {
+            scripting_enabled: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            serialize_with_opts(&div, no_scripting, TraversalScope::ChildrenOnly(None)).unwrap(),
+            "<p>One</p><noscript>&lt;b&gt;</noscript>"
+        );
+    }
+
+    #[test]
+    fn test_whitespace_trimming() {
+        use html5ever::driver::ParseOpts;
+        use html5ever::parse_document;
+        use html5ever::tendril::TendrilSink;
+
+        let html = "<!DOCTYPE html>\n<html>\n  <body>\n    <ul>\n      <li>One</li>\n      <li>Two</li>\n    </ul>\n\
+            <p>Some <b>bold</b> <i>text</i></p>\n    <pre>\n  <span>code</span>\n</pre>\n  </body>\n</html>\n";
+        let parse = |trim| {
+            parse_document(Dom::default().with_whitespace_trimming(trim), ParseOpts::default())
+                .one(html)
+        };
+
+        let full = parse(false);
+        let trimmed = parse(true);
+        assert!(trimmed.stats().node_count < full.stats().node_count);
+        assert_eq!(full.stats().node_count, 29);
+        assert_eq!(trimmed.stats().node_count, 22);
+
+        // Whitespace between words and inside `<pre>` is kept
+        let body = trimmed.body().unwrap();
+        assert_eq!(
+            serialize_to_string(&body).unwrap(),
+            "<ul><li>One</li><li>Two</li></ul><p>Some <b>bold</b> <i>text</i></p>\
+            <pre>  <span>code</span>\n</pre>"
+        );
+    }
+
+    #[test]
+    fn test_dom_debug() {
+        let dom = parse_html("<!DOCTYPE html><p>One</p><p>Two</p>");
+        assert_eq!(
+            format!("{:?}", dom),
+            "Dom { nodes: 9, elements: 5, quirks_mode: NoQuirks, errors: 0 }"
+        );
+
+        let dump = dom.dump_tree();
+        assert!(dump.starts_with("Node {"));
+        assert!(dump.contains("\"Two\""));
+    }
+
+    #[test]
+    fn test_root() {
+        let dom = parse_html("<div><p>Text</p></div>");
+        let p = select(&dom.tree, "p").remove(0);
+        assert!(p.root().same_node(&dom.tree));
+        assert!(dom.tree.root().same_node(&dom.tree));
+
+        let parent = Node::new_element("div", &[]);
+        let middle = Node::new_element("span", &[]);
+        let leaf = Node::new_text("Text".to_string());
+        append(&parent, middle.clone());
+        append(&middle, leaf.clone());
+        assert!(leaf.root().same_node(&parent));
+
+        // The walk stops at a dropped parent
+        drop(parent);
+        assert!(leaf.root().same_node(&middle));
+    }
+
+    #[test]
+    fn test_document_order() {
+        let dom = parse_html(
+            "<div id='a'><p id='b'><i id='c'></i></p><p id='d'><b id='e'></b></p></div>",
+        );
+        let node = |id: &str| select(&dom.tree, &format!("#{}", id)).remove(0);
+        let (a, b, c, d, e) = (node("a"), node("b"), node("c"), node("d"), node("e"));
+
+        // The same node, ancestors, siblings and cousins
+        assert_eq!(document_order(&b, &b), Some(Ordering::Equal));
+        assert_eq!(document_order(&a, &c), Some(Ordering::Less));
+        assert_eq!(document_order(&c, &a), Some(Ordering::Greater));
+        assert_eq!(document_order(&dom.tree, &e), Some(Ordering::Less));
+        assert_eq!(document_order(&b, &d), Some(Ordering::Less));
+        assert_eq!(document_order(&d, &b), Some(Ordering::Greater));
+        assert_eq!(document_order(&c, &e), Some(Ordering::Less));
+        assert_eq!(document_order(&e, &c), Some(Ordering::Greater));
+        assert_eq!(document_order(&c, &d), Some(Ordering::Less));
+
+        let mut nodes = [e, a, d, c.clone(), b.clone()];
+        nodes.sort_by(|x, y| document_order(x, y).unwrap());
+        let ids: Vec<_> = nodes.iter().filter_map(|n| n.get_attribute("id")).collect();
+        assert_eq!(ids, ["a", "b", "c", "d", "e"]);
+
+        // Nodes of different trees can't be compared
+        let detached = Node::new_element("p", &[]);
+        assert_eq!(document_order(&c, &detached), None);
+        let other = parse_html("<p id='b'></p>");
+        assert_eq!(document_order(&b, &select(&other.tree, "#b")[0]), None);
+    }
+
+    #[test]
+    fn test_parent_and_index() {
+        let parent = Node::new_element("ul", &[]);
+        let first = Node::new_element("li", &[]);
+        let second = Node::new_element("li", &[]);
+        append(&parent, first.clone());
+        append(&parent, second.clone());
+
+        let (found, index) = parent_and_index(&second).unwrap();
+        assert!(found.same_node(&parent));
+        assert_eq!(index, 1);
+        assert!(parent_and_index(&parent).is_none());
+
+        // Not listed among the parent's children
+        let orphan = Node::new_element("li", &[]);
+        orphan.parent.set(Some(Rc::downgrade(&parent)));
+        assert!(parent_and_index(&orphan).is_none());
+
+        // Dangling parent
+        drop(found);
+        drop(parent);
+        assert!(parent_and_index(&first).is_none());
+    }
+
+    #[test]
+    fn test_same_node() {
+        let a = Node::new_element("p", &[]);
+        let b = Node::new_element("p", &[]);
+        assert!(a == b);
+        assert!(!a.same_node(&b));
+        assert!(a.same_node(&a.clone()));
+    }
+
+    #[test]
+    fn test_dom_stats() {
+        let dom = parse_html("<!DOCTYPE html><p>Hi <b>caf\u{e9}</b></p><!-- c -->");
+        // document > doctype, html > head, body > p > "Hi ", b > "café", comment
+        assert_eq!(
+            dom.stats(),
+            DomStats {
+                node_count: 10,
+                element_count: 5,
+                text_bytes: 3 + 5,
+                max_depth: 5,
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_text_content() {
+        let dom = parse_html("<p>Hello <b>big</b> world<!-- comment --></p><p>!</p>");
+        let mut output = Vec::new();
+        write_text_content(&dom.tree, &mut output).unwrap();
+        assert_eq!(output, b"Hello big world!");
+        assert_eq!(text_content(&dom.tree), "Hello big world!");
+    }
+
+    #[test]
+    fn test_attribute_accessors() {
+        let node = Node::new_element("a", &[("href", "/old"), ("class", "nav  Active")]);
+
+        assert_eq!(node.get_attribute("href").as_deref(), Some("/old"));
+        assert_eq!(node.get_attribute("title"), None);
+
+        node.set_attribute("href", "/new");
+        node.set_attribute("title", "Home");
+        assert_eq!(node.get_attribute("href").as_deref(), Some("/new"));
+        assert_eq!(node.get_attribute("title").as_deref(), Some("Home"));
+
+        assert!(node.remove_attribute("title"));
+        assert!(!node.remove_attribute("title"));
+        assert_eq!(node.get_attribute("title"), None);
+
+        assert!(node.has_class("nav"));
+        assert!(node.has_class("Active"));
+        assert!(!node.has_class("active"));
+        assert!(!node.has_class("nav  Active"));
+
+        // Non-elements have no attributes
+        let text = Node::new_text("text".to_string());
+        text.set_attribute("class", "nav");
+        assert_eq!(text.get_attribute("class"), None);
+        assert!(!text.remove_attribute("class"));
+        assert!(!text.has_class("nav"));
+    }
+
+    #[test]
+    fn test_node_text_content() {
+        let dom = parse_html(
+            "<div><p>Hello<b>world</b></p> <script>track()</script><style>p {}</style>\n</div>",
+        );
+        let div = select(&dom.tree, "div").remove(0);
+        let p = select(&div, "p").remove(0);
+
+        assert_eq!(p.text_content(), "Helloworld");
+        assert_eq!(div.text_content(), "Helloworld track()p {}\n");
+        assert_eq!(p.text_content_separated(" ", false), "Hello world");
+        assert_eq!(
+            div.text_content_separated(" | ", false),
+            "Hello | world | track() | p {}"
+        );
+        assert_eq!(div.text_content_separated(" ", true), "Hello world");
+    }
+
+    #[test]
+    fn test_text_content_foreign_content() {
+        // Text in SVG and MathML is parsed into ordinary text nodes under foreign elements,
+        // and CDATA sections in foreign content become text as well
+        let dom = parse_html(
+            "<p>Chart: <svg><text>Label</text><title><![CDATA[a < b]]></title></svg></p>\
+            <math><mi>x</mi></math>",
+        );
+        let text = select(&dom.tree, "text").remove(0);
+        assert!(matches!(&text.data, NodeData::Element { name, .. } if name.ns == ns!(svg)));
+
+        assert_eq!(text_content(&dom.tree), "Chart: Labela < bx");
+        assert_eq!(visible_text(&dom.tree), "Chart: Labela < bx");
+    }
+
+    #[test]
+    fn test_normalized_text() {
+        let dom = parse_html(
+            "<p>  Some \n  text <b>here</b></p>\
+            <address>1 Main St\n  Springfield</address><pre>a\n  b</pre><p>x<br>y</p>",
+        );
+
+        assert_eq!(
+            normalized_text(&dom.tree, DEFAULT_PRESERVE_TAGS),
+            "Some text here\n1 Main St Springfield\na\n  b\nx\ny"
+        );
+        assert_eq!(
+            normalized_text(&dom.tree, &["address"]),
+            "Some text here\n1 Main St\n  Springfield\na b\nx\ny"
+        );
+
+        let dom = parse_html("<p>encyclo<wbr>pedia de\u{00AD}hy&shy;phen\u{00AD}ated</p>");
+        assert_eq!(
+            normalized_text(&dom.tree, DEFAULT_PRESERVE_TAGS),
+            "encyclopedia dehyphenated"
+        );
+    }
+
+    #[test]
+    fn test_visible_text() {
+        let dom = parse_html(
+            "<p>Shown</p><p hidden>Hidden <b>deep</b></p>\
+            <div aria-hidden=\"true\">Icon</div><div aria-hidden=\"false\"> too</div>\
+            <template><p>Template</p></template><script>var x;</script>",
+        );
+        assert_eq!(visible_text(&dom.tree), "Shown too");
+    }
+
+    #[test]
+    fn test_remove_attributes() {
+        let dom = parse_html(
+            r#"<div onclick="a()" class="box"><button onmouseover="b()" onClick="c()" type="button">Go</button></div>"#,
+        );
+
+        let removed = remove_attributes(&dom.tree, |name| name.starts_with("on"));
+        assert_eq!(removed, 3);
+        assert_eq!(
+            serialize_to_string(&dom.tree).unwrap(),
+            r#"<html><head></head><body><div class="box"><button type="button">Go</button></div></body></html>"#
+        );
+    }
 }
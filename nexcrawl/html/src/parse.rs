@@ -0,0 +1,224 @@
+//! Parse HTML into a [`Dom`]
+
+use std::borrow::Cow;
+use std::io;
+
+use encoding_rs::{Encoding, UTF_8};
+use html5ever::driver::ParseOpts;
+use html5ever::tendril::TendrilSink;
+use html5ever::{LocalName, QualName, ns};
+
+use crate::node::{Dom, Handle};
+
+/// Number of leading bytes scanned for a `<meta charset>` declaration
+const META_PRESCAN_LIMIT: usize = 1024;
+
+/// The top-level nodes of a parsed HTML fragment, see [`parse_fragment`]
+#[derive(Debug)]
+pub struct Fragment {
+    /// The parsed nodes, in document order; they have no parent
+    pub nodes: Vec<Handle>,
+    /// Errors that occurred during parsing
+    pub errors: Vec<Cow<'static, str>>,
+}
+
+/// Parse an HTML document from a string
+pub fn parse_html(html: &str) -> Dom {
+    parse_document(html)
+}
+
+/// Parse an HTML document from a string
+///
+/// The same as [`parse_html`]. Malformed input is still parsed the way a browser would; the
+/// problems the parser recovered from are listed in [`Dom::errors`].
+pub fn parse_document(html: &str) -> Dom {
+    html5ever::parse_document(Dom::default(), ParseOpts::default())
+        .from_utf8()
+        .one(html.as_bytes())
+}
+
+/// Parse an HTML fragment as if it were the content of a `context` element
+///
+/// The context decides how the markup is parsed: `<td>` cells only survive in a `"tr"`
+/// context, for example, and are dropped as misplaced in a `"div"` context.
+///
+/// # Examples
+///
+/// ```
+/// use nexcrawl_html::parse_fragment;
+///
+/// let fragment = parse_fragment("<td>1</td><td>2</td>", "tr");
+/// assert_eq!(fragment.nodes.len(), 2);
+/// ```
+pub fn parse_fragment(html: &str, context: &str) -> Fragment {
+    let dom = parse_fragment_dom(html, context);
+    let Some(root) = dom.tree.children.borrow().first().cloned() else {
+        return Fragment {
+            nodes: Vec::new(),
+            errors: dom.errors.take(),
+        };
+    };
+
+    let nodes = root.children.take();
+    for node in &nodes {
+        node.parent.set(None);
+    }
+    Fragment {
+        nodes,
+        errors: dom.errors.take(),
+    }
+}
+
+/// Parse a UTF-8 HTML document from a reader
+///
+/// The input is fed to the parser in chunks as it is read, so the whole document is never
+/// held in memory as a `String`. Errors from the reader are returned as is.
+pub fn parse_html_reader<R: io::Read>(mut reader: R) -> io::Result<Dom> {
+    html5ever::parse_document(Dom::default(), ParseOpts::default())
+        .from_utf8()
+        .read_from(&mut reader)
+}
+
+/// Parse an HTML fragment as if it were the content of a `<body>` element
+///
+/// The parsed nodes are the children of the `<html>` element below the document root.
+pub(crate) fn parse_body_fragment(html: &str) -> Dom {
+    parse_fragment_dom(html, "body")
+}
+
+/// Parse an HTML fragment in the context of the named element, keeping the whole `Dom`
+fn parse_fragment_dom(html: &str, context: &str) -> Dom {
+    html5ever::parse_fragment(
+        Dom::default(),
+        ParseOpts::default(),
+        QualName::new(None, ns!(html), LocalName::from(context.to_ascii_lowercase())),
+        Vec::new(),
+        false,
+    )
+    .from_utf8()
+    .one(html.as_bytes())
+}
+
+/// Parse an HTML document from raw bytes in the given encoding
+///
+/// The encoding is chosen in the following order:
+/// 1. A byte order mark at the start of `bytes`
+/// 2. A `<meta charset>` (or `http-equiv` `content-type`) declaration in the first 1024 bytes
+/// 3. The `encoding` label (e.g. `"windows-1252"`, `"shift_jis"`)
+///
+/// If none of them names an encoding known to the [Encoding Standard], UTF-8 is used.
+/// Malformed byte sequences are replaced with U+FFFD.
+///
+/// [Encoding Standard]: https://encoding.spec.whatwg.org/#names-and-labels
+pub fn parse_html_bytes(bytes: &[u8], encoding: &str) -> Dom {
+    let encoding = prescan_meta_charset(bytes)
+        .or_else(|| Encoding::for_label(encoding.trim().as_bytes()))
+        .unwrap_or(UTF_8);
+
+    // `decode` sniffs the BOM, which takes precedence over the chosen encoding
+    let (html, _, _) = encoding.decode(bytes);
+    parse_html(&html)
+}
+
+/// Find an encoding declared by a `<meta>` tag near the start of the document
+fn prescan_meta_charset(bytes: &[u8]) -> Option<&'static Encoding> {
+    let head = &bytes[..bytes.len().min(META_PRESCAN_LIMIT)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+
+    let mut rest = head.as_str();
+    while let Some(start) = rest.find("<meta") {
+        rest = &rest[start + "<meta".len()..];
+        let tag = &rest[..rest.find('>').unwrap_or(rest.len())];
+
+        if let Some(pos) = tag.find("charset=") {
+            let value = tag[pos + "charset=".len()..].trim_start_matches(['"', '\'', ' ']);
+            let label = value
+                .split(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace())
+                .next()
+                .unwrap_or_default();
+            if let Some(encoding) = Encoding::for_label(label.as_bytes()) {
+                return Some(encoding);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{NodeData, serialize_to_string};
+    use crate::selector::select;
+
+    fn body_html(html: &[u8], encoding: &str) -> String {
+        let dom = parse_html_bytes(html, encoding);
+        let body = select(&dom.tree, "body");
+        serialize_to_string(&body[0]).unwrap()
+    }
+
+    #[test]
+    fn test_parse_html_bytes_windows_1252() {
+        // "café" with 0xE9 for 'é' in windows-1252
+        let html = b"<p>caf\xE9</p>";
+        assert_eq!(body_html(html, "windows-1252"), "<p>café</p>");
+    }
+
+    #[test]
+    fn test_parse_html_bytes_meta_charset_override() {
+        // "日本" in shift_jis
+        let html = b"<meta charset=\"shift_jis\"><p>\x93\xfa\x96\x7b</p>";
+        assert_eq!(body_html(html, "utf-8"), "<p>日本</p>");
+    }
+
+    #[test]
+    fn test_parse_html_bytes_unknown_encoding() {
+        let html = "<p>café</p>";
+        assert_eq!(body_html(html.as_bytes(), "not-an-encoding"), "<p>café</p>");
+    }
+
+    #[test]
+    fn test_parse_document_errors() {
+        let dom = parse_document("<!DOCTYPE html><p>Well formed</p>");
+        assert!(dom.errors.borrow().is_empty());
+
+        let dom = parse_document("<p>Unclosed <b>tags</p></i>");
+        assert!(!dom.errors.borrow().is_empty());
+        assert_eq!(select(&dom.tree, "p b").len(), 1);
+    }
+
+    #[test]
+    fn test_parse_fragment_context() {
+        let cells = parse_fragment("<td>1</td><td>2</td>", "tr");
+        assert_eq!(cells.nodes.len(), 2);
+        assert!(cells.nodes.iter().all(|node| node.get_parent().is_none()));
+        assert_eq!(serialize_to_string(&cells.nodes[1]).unwrap(), "2");
+        assert!(cells.errors.is_empty());
+
+        // Table cells are misplaced outside of a table row
+        let text = parse_fragment("<td>1</td><td>2</td>", "div");
+        assert_eq!(text.nodes.len(), 1);
+        assert!(matches!(text.nodes[0].data, NodeData::Text { .. }));
+        assert!(!text.errors.is_empty());
+
+        let nodes = parse_fragment("<p>A</p> <p>B</p>", "DIV").nodes;
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(select(&nodes[2], "p").len(), 1);
+    }
+
+    #[test]
+    fn test_parse_html_reader() {
+        let html = "<p>caf\u{e9}</p>".repeat(2000);
+        let dom = parse_html_reader(io::Cursor::new(html.as_bytes())).unwrap();
+        assert_eq!(serialize_to_string(&dom.body().unwrap()).unwrap(), html);
+
+        struct FailingReader;
+        impl io::Read for FailingReader {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("broken pipe"))
+            }
+        }
+        let error = parse_html_reader(FailingReader).err().unwrap();
+        assert_eq!(error.to_string(), "broken pipe");
+    }
+}
@@ -2,13 +2,23 @@
 
 extern crate html5ever;
 
-use crate::node::{Dom, Handle, Node, NodeData, serialize_to_string};
+use crate::meta::extract_metadata;
+use crate::node::{
+    Dom, Handle, Node, NodeData, NodeRanges, VOID_ELEMENTS, serialize_to_string_lossy,
+    serialize_with_ranges,
+};
+use crate::table::{pipe_table, table_grid};
+use html5ever::data::{C1_REPLACEMENTS, NAMED_ENTITIES};
 use html5ever::driver::ParseOpts;
 use html5ever::parse_document;
+use html5ever::{LocalName, QualName};
 use html5ever::{tendril::TendrilSink, tree_builder::TreeBuilderOpts};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::io;
 use std::rc::Rc;
 
-/// Tags that represents inline text styles
+/// Default [`PreprocessConfig::inline_tags`]: tags that represents inline text styles
 const INLINE_TAGS: &[&str] = &[
     "b",
     "blockquote",
@@ -19,7 +29,17 @@ const INLINE_TAGS: &[&str] = &[
     "strike",
     "strong",
 ];
-/// Tags that are forbidden and should be removed from the HTML.
+/// Tags that start a new block of text
+const BLOCK_TAGS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "dd", "details", "div", "dl", "dt", "fieldset",
+    "figcaption", "figure", "form", "h1", "h2", "h3", "h4", "h5", "h6", "li", "main", "ol", "p",
+    "pre", "section", "summary", "table", "ul",
+];
+/// Block tags that only make sense inside their parent, so a wrapper is never replaced by
+/// them in [`PreprocessConfig::collapse_single_child_wrappers`]
+const PARENT_BOUND_TAGS: &[&str] = &["li", "dd", "dt", "summary", "figcaption"];
+/// Default [`PreprocessConfig::forbidden_tags`]: tags that are removed from the HTML along
+/// with their contents.
 const FORBIDDEN_TAGS: &[&str] = &[
     "script", "noscript", "iframe", "object", "embed", "applet", "link", "meta", "style", "svg",
     "canvas", "audio", "video", "button", "nav", "header", "footer", "hr", "br",
@@ -29,12 +49,12 @@ const FORBIDDEN_TAGS: &[&str] = &[
 /// * Remove unnecessary spaces, newlines, and tabs
 /// * Decode HTML entities like &nbsp;, &amp;, etc.
 /// * Remove duplicated whitespace
+/// * Remove soft hyphens
 fn preprocess_text(text: &str) -> String {
-    let mut result = text.trim().to_string();
+    let mut result = decode_entities(text.trim()).replace('\u{00AD}', "");
 
     // Replace all whitespace characters with single spaces
     result = result
-        .replace("&nbsp;", " ")
         .replace("\n", " ")
         .replace("\r", " ")
         .replace("\t", " ")
@@ -48,29 +68,581 @@ fn preprocess_text(text: &str) -> String {
     result.trim().to_string()
 }
 
+/// Maximum length of a character reference after the `&`, including the `;`
+const MAX_REFERENCE_LEN: usize = 32;
+
+/// Decode named (`&amp;`) and numeric (`&#39;`, `&#x27;`) character references
+///
+/// Only references terminated by `;` are decoded; anything else, like a bare `&` or an
+/// unknown name, is left as is. Numeric references to code points that are not allowed in
+/// HTML are replaced the way the HTML parser does, with the Windows-1252 character for C1
+/// controls and U+FFFD otherwise.
+fn decode_entities(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        match decode_reference(&rest[1..]) {
+            Some((decoded, len)) => {
+                output.push_str(&decoded);
+                rest = &rest[1 + len..];
+            }
+            None => {
+                output.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Decode the character reference at the start of `text`, which follows a `&`
+///
+/// Returns the decoded characters and the length of the reference including its `;`.
+fn decode_reference(text: &str) -> Option<(String, usize)> {
+    // The longest entity name has 31 characters; this also bounds the scan for the `;`
+    let end = text.bytes().take(MAX_REFERENCE_LEN).position(|b| b == b';')?;
+    let reference = &text[..end];
+
+    if let Some(number) = reference.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) if !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                u32::from_str_radix(hex, 16).unwrap_or(u32::MAX)
+            }
+            None if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) => {
+                number.parse().unwrap_or(u32::MAX)
+            }
+            _ => return None,
+        };
+        let decoded = match code {
+            0x80..=0x9F => C1_REPLACEMENTS[(code - 0x80) as usize].unwrap_or('\u{FFFD}'),
+            0 => '\u{FFFD}',
+            _ => char::from_u32(code).unwrap_or('\u{FFFD}'),
+        };
+        return Some((decoded.to_string(), end + 1));
+    }
+
+    if reference.is_empty() || !reference.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let &(first, second) = NAMED_ENTITIES.get(&text[..=end])?;
+    let decoded = [first, second]
+        .into_iter()
+        .filter(|&c| c != 0)
+        .filter_map(char::from_u32)
+        .collect();
+    Some((decoded, end + 1))
+}
+
+/// Whether the text only contains whitespace and zero-width characters
+fn is_blank(text: &str) -> bool {
+    text.chars()
+        .all(|c| c.is_whitespace() || matches!(c, '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}'))
+}
+
+/// Whether the character is an invisible format character removed by
+/// [`PreprocessConfig::strip_invisible_controls`]
+fn is_invisible_control(c: char) -> bool {
+    matches!(
+        c,
+        '\u{061C}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{2069}'
+            | '\u{FEFF}'
+    )
+}
+
+/// Remove blank block elements below the node and collapse runs of blank text nodes
+///
+/// Returns whether the node itself only contains blank text after the pass.
+fn collapse_empty_blocks(node: &Handle) -> bool {
+    let children = std::mem::take(&mut *node.children.borrow_mut());
+    let mut kept: Vec<Handle> = Vec::with_capacity(children.len());
+    for child in children {
+        match &child.data {
+            NodeData::Text { text } if is_blank(&text.borrow()) => {
+                let previous_blank = kept.last().is_some_and(
+                    |last| matches!(&last.data, NodeData::Text { text } if is_blank(&text.borrow())),
+                );
+                if !previous_blank {
+                    child.set_text(" ");
+                    kept.push(child);
+                }
+            }
+            NodeData::Element { name, .. } => {
+                let blank = collapse_empty_blocks(&child);
+                if !(blank && BLOCK_TAGS.contains(&name.local.as_ref())) {
+                    kept.push(child);
+                }
+            }
+            _ => kept.push(child),
+        }
+    }
+
+    let blank = kept.iter().all(|child| match &child.data {
+        NodeData::Text { text } => is_blank(&text.borrow()),
+        _ => false,
+    });
+    node.children.replace(kept);
+    blank
+}
+
+/// How emoji in text are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmojiPolicy {
+    /// Keep emoji as they are
+    #[default]
+    Keep,
+    /// Remove emoji, including flags and sequences joined with U+200D
+    Remove,
+    /// Replace common emoji with a short name, e.g. `😀` with `:grinning:` and flags with
+    /// `:flag_us:`. Other emoji are kept as they are.
+    ReplaceWithName,
+}
+
+/// Names of common emoji for [`EmojiPolicy::ReplaceWithName`]
+const EMOJI_NAMES: &[(char, &str)] = &[
+    ('😀', "grinning"),
+    ('😁', "grin"),
+    ('😂', "joy"),
+    ('😃', "smiley"),
+    ('😄', "smile"),
+    ('😉', "wink"),
+    ('😊', "blush"),
+    ('😍', "heart_eyes"),
+    ('😎', "sunglasses"),
+    ('😢', "cry"),
+    ('😭', "sob"),
+    ('😡', "rage"),
+    ('🙂', "slightly_smiling_face"),
+    ('🙏', "pray"),
+    ('🤔', "thinking"),
+    ('👍', "thumbsup"),
+    ('👎', "thumbsdown"),
+    ('👏', "clap"),
+    ('👀', "eyes"),
+    ('🔥', "fire"),
+    ('🎉', "tada"),
+    ('🚀', "rocket"),
+    ('💯', "100"),
+    ('💡', "bulb"),
+    ('📌', "pushpin"),
+    ('❤', "heart"),
+    ('⭐', "star"),
+    ('✅', "white_check_mark"),
+    ('❌', "x"),
+    ('⚠', "warning"),
+];
+
+impl EmojiPolicy {
+    /// Apply the policy to a text
+    fn apply(self, text: &str) -> String {
+        if self == EmojiPolicy::Keep {
+            return text.to_string();
+        }
+
+        let mut output = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if !is_emoji(ch) {
+                output.push(ch);
+                continue;
+            }
+
+            // A flag is a pair of regional indicators
+            let mut flag = None;
+            if is_regional_indicator(ch)
+                && let Some(&next) = chars.peek()
+                && is_regional_indicator(next)
+            {
+                chars.next();
+                flag = Some([ch, next]);
+            }
+
+            // Skip modifiers and the rest of a joined sequence
+            while let Some(&next) = chars.peek() {
+                if is_emoji_modifier(next) {
+                    chars.next();
+                } else if next == '\u{200D}' {
+                    chars.next();
+                    chars.next_if(|&c| is_emoji(c));
+                } else {
+                    break;
+                }
+            }
+
+            if self == EmojiPolicy::ReplaceWithName {
+                if let Some(flag) = flag {
+                    let code = flag
+                        .iter()
+                        .map(|&c| (b'a' + (c as u32 - 0x1F1E6) as u8) as char)
+                        .collect::<String>();
+                    output.push_str(&format!(":flag_{}:", code));
+                } else if let Some((_, name)) = EMOJI_NAMES.iter().find(|(emoji, _)| *emoji == ch) {
+                    output.push_str(&format!(":{}:", name));
+                } else {
+                    output.push(ch);
+                }
+            }
+        }
+        output
+    }
+}
+
+/// Whether the character is an emoji or pictograph, based on the Unicode emoji blocks
+fn is_emoji(ch: char) -> bool {
+    matches!(ch,
+        '\u{1F000}'..='\u{1FAFF}' // Tiles, cards, enclosed characters, pictographs, emoticons
+        | '\u{2600}'..='\u{27BF}' // Miscellaneous symbols and dingbats
+        | '\u{2B00}'..='\u{2BFF}' // Arrows and stars such as ⭐
+    )
+}
+
+fn is_regional_indicator(ch: char) -> bool {
+    matches!(ch, '\u{1F1E6}'..='\u{1F1FF}')
+}
+
+/// Whether the character modifies the emoji before it (variation selector, skin tone, ...)
+fn is_emoji_modifier(ch: char) -> bool {
+    matches!(ch,
+        '\u{FE0F}' | '\u{20E3}' | '\u{1F3FB}'..='\u{1F3FF}' | '\u{E0020}'..='\u{E007F}'
+    )
+}
+
+/// How `<time>` elements with a `datetime` attribute are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeHandling {
+    /// Keep only the human readable text, e.g. `Jan 2`
+    #[default]
+    Text,
+    /// Replace the text with the `datetime` value, e.g. `2024-01-02`
+    Datetime,
+    /// Keep the text followed by the `datetime` value, e.g. `Jan 2 (2024-01-02)`
+    TextAndDatetime,
+}
+
+/// How `<a>` elements are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LinkHandling {
+    /// Keep the `<a>` element like any other element
+    Keep,
+    /// Remove the link together with its text
+    #[default]
+    Remove,
+    /// Replace the link with its text followed by the target, e.g. `text (https://...)`
+    Inline,
+    /// Replace the link with a Markdown link, e.g. `[text](https://...)`
+    Markdown,
+}
+
+/// How `<table>` elements are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableHandling {
+    /// Keep the table markup like any other element
+    Keep,
+    /// Remove the table, see [`PreprocessConfig::keep_table_caption`]
+    #[default]
+    Remove,
+    /// Replace the table with a Markdown pipe table in a `<pre>`
+    Markdown,
+    /// Replace the table with tab-separated rows in a `<pre>`
+    Tsv,
+}
+
+/// Append the text of all descendants, separated by spaces
+fn collect_text(node: &Handle, out: &mut String) {
+    for child in node.children.borrow().iter() {
+        match &child.data {
+            NodeData::Text { text } => {
+                out.push_str(&text.borrow());
+                out.push(' ');
+            }
+            _ => collect_text(child, out),
+        }
+    }
+}
+
+/// Text with its whitespace collapsed, remembering whether the original text had whitespace
+/// at its edges so that the spacing between inline elements can be reconstructed
+#[derive(Debug, PartialEq)]
+struct NormalizedText {
+    text: String,
+    had_leading_ws: bool,
+    had_trailing_ws: bool,
+}
+
+impl NormalizedText {
+    fn new(raw: &str) -> Self {
+        Self {
+            text: preprocess_text(raw),
+            had_leading_ws: raw.starts_with(char::is_whitespace),
+            had_trailing_ws: raw.ends_with(char::is_whitespace),
+        }
+    }
+}
+
+/// Check whether the text of a node starts and ends with whitespace
+fn edge_whitespace(node: &Handle) -> (bool, bool) {
+    match &node.data {
+        NodeData::Text { text } => {
+            let normalized = NormalizedText::new(&text.borrow());
+            (normalized.had_leading_ws, normalized.had_trailing_ws)
+        }
+        _ => (
+            first_text(node).is_some_and(|t| NormalizedText::new(&t).had_leading_ws),
+            last_text(node).is_some_and(|t| NormalizedText::new(&t).had_trailing_ws),
+        ),
+    }
+}
+
+/// Get the first non-empty descendant text of a node
+fn first_text(node: &Handle) -> Option<String> {
+    node.children.borrow().iter().find_map(|child| match &child.data {
+        NodeData::Text { text } if !text.borrow().is_empty() => Some(text.borrow().to_string()),
+        _ => first_text(child),
+    })
+}
+
+/// Get the last non-empty descendant text of a node
+fn last_text(node: &Handle) -> Option<String> {
+    node.children.borrow().iter().rev().find_map(|child| match &child.data {
+        NodeData::Text { text } if !text.borrow().is_empty() => Some(text.borrow().to_string()),
+        _ => last_text(child),
+    })
+}
+
+#[derive(Debug, Clone)]
 pub struct PreprocessConfig {
-    pub remove_links: bool,
+    /// How `<a>` elements are rendered. Links without an `href` are replaced by their text
+    /// with [`LinkHandling::Inline`] and [`LinkHandling::Markdown`].
+    pub link_handling: LinkHandling,
     pub remove_images: bool,
-    pub remove_tables: bool,
+
+    /// How `<table>` elements are rendered.
+    ///
+    /// [`TableHandling::Markdown`] and [`TableHandling::Tsv`] rebuild the table as a grid of
+    /// cell texts, one row per `<tr>`, padding short rows with empty cells. The first row of
+    /// the `<thead>`, or else the first row, comes first and is the Markdown header, like in
+    /// [`MarkdownEmitter`](crate::markdown::MarkdownEmitter). The text is wrapped
+    /// in a `<pre>` so that its line breaks and tabs survive whitespace normalization.
+    pub table_handling: TableHandling,
+
+    /// Tags to rename while preprocessing (e.g. `b` -> `strong`).
+    /// Renaming happens before compaction, so renamed tags are treated as their new name.
+    pub rename_tags: HashMap<String, String>,
+
+    /// Tags removed together with their contents, e.g. `script`, `nav` or `footer`.
+    ///
+    /// Use [`PreprocessConfig::allow_tag`] to keep one of the defaults.
+    pub forbidden_tags: HashSet<String>,
+
+    /// Tags of inline text styles, e.g. `b` or `em`. An inline element that only contains
+    /// text is replaced by its text.
+    ///
+    /// Matched after renaming with `rename_tags`.
+    pub inline_tags: HashSet<String>,
+
+    /// How `<time datetime="...">` elements are rendered
+    pub time_handling: TimeHandling,
+
+    /// Keep the contents of `<noscript>` instead of removing it.
+    ///
+    /// `<noscript>` is treated as a transparent wrapper: its children are processed as if they
+    /// were children of its parent. [`Preprocessor::preprocess_html`] parses with scripting
+    /// disabled in this mode so the contents are parsed as markup. Trees parsed with scripting
+    /// enabled (the html5ever default) hold the contents as a single raw text node, which is
+    /// then kept as text.
+    pub keep_noscript: bool,
+
+    /// Render `<abbr title="...">` as `ABBR (title)` on the first occurrence of each
+    /// abbreviation. Later occurrences are kept as they are.
+    pub expand_abbreviations: bool,
+
+    /// Stop processing after visiting this many nodes of the source tree.
+    ///
+    /// A safeguard against adversarial input. Nodes beyond the budget are dropped, so the
+    /// output is partial but still well-formed. Unlimited by default.
+    pub max_nodes: Option<usize>,
+
+    /// Remove block elements that only contain blank text after processing, and collapse runs
+    /// of blank text nodes into a single space.
+    ///
+    /// Besides whitespace, zero-width characters (e.g. U+200B, U+FEFF) count as blank, since
+    /// they survive the regular whitespace trimming.
+    pub collapse_empty_blocks: bool,
+
+    /// Replace a block element whose only content is a single block element with that
+    /// element, whatever their tags, so `<div><section><p>text</p></section></div>` becomes
+    /// `<p>text</p>`.
+    ///
+    /// Wrappers with one of the `semantic_attributes` are kept, and so are the parents of
+    /// elements like `<li>` that need them.
+    pub collapse_single_child_wrappers: bool,
+
+    /// How emoji in text nodes are handled
+    pub emoji_policy: EmojiPolicy,
+
+    /// When [`TableHandling::Remove`] removes a table, keep the text of its `<caption>` as a
+    /// `<p>`.
+    pub keep_table_caption: bool,
+
+    /// Remove the attributes of kept elements, except for `semantic_attributes`.
+    ///
+    /// Off by default, in which case every attribute is kept.
+    pub strip_attributes: bool,
+
+    /// Attributes that carry meaning for a reader, kept by `strip_attributes`.
+    ///
+    /// Defaults to `lang`, `datetime` and `alt`. Add e.g. `href` to keep the targets of links.
+    /// Has no effect unless `strip_attributes` is set, since all attributes are kept then.
+    pub semantic_attributes: HashSet<String>,
+
+    /// Remove invisible format characters from text: zero-width spaces and joiners
+    /// (U+200B..U+200D, U+2060..U+2064, U+FEFF), and bidi marks, embeddings, overrides and
+    /// isolates (U+061C, U+200E, U+200F, U+202A..U+202E, U+2066..U+2069).
+    ///
+    /// These characters can hide text from readers or reorder it on screen, e.g. an RLO
+    /// (U+202E) makes `exe.txt` look like `txt.exe`, and a zero-width space splits a word into
+    /// different tokens without any visible change. Off by default since some scripts and
+    /// emoji sequences rely on joiners; emoji joined with U+200D fall apart into their parts.
+    pub strip_invisible_controls: bool,
+
+    /// Replace `<hr>` with this text, e.g. `"---"`, instead of removing it, so the section
+    /// boundaries of an article survive.
+    ///
+    /// Takes precedence over `forbidden_tags`. Consecutive `<hr>` elements become a single
+    /// separator. `None` by default.
+    pub thematic_break: Option<String>,
 }
 
 impl Default for PreprocessConfig {
     fn default() -> Self {
         Self {
-            remove_links: true,
+            link_handling: LinkHandling::default(),
             remove_images: true,
-            remove_tables: true,
+            table_handling: TableHandling::default(),
+            rename_tags: HashMap::new(),
+            forbidden_tags: FORBIDDEN_TAGS.iter().map(|tag| tag.to_string()).collect(),
+            inline_tags: INLINE_TAGS.iter().map(|tag| tag.to_string()).collect(),
+            time_handling: TimeHandling::default(),
+            keep_noscript: false,
+            expand_abbreviations: false,
+            max_nodes: None,
+            collapse_empty_blocks: false,
+            collapse_single_child_wrappers: false,
+            emoji_policy: EmojiPolicy::default(),
+            keep_table_caption: false,
+            strip_attributes: false,
+            semantic_attributes: ["lang", "datetime", "alt"].map(String::from).into(),
+            strip_invisible_controls: false,
+            thematic_break: None,
         }
     }
 }
 
+impl PreprocessConfig {
+    /// Keep a tag that is forbidden by default, such as `nav` on a site where navigation is
+    /// the content
+    pub fn allow_tag(&mut self, tag: &str) -> &mut Self {
+        self.forbidden_tags.remove(&tag.to_ascii_lowercase());
+        self
+    }
+}
+
+/// Why preprocessing produced no output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyReason {
+    /// The input was empty or only contained whitespace
+    EmptyInput,
+    /// The input had content, but all of it was removed by the configuration
+    AllRemoved,
+}
+
+/// The result of [`Preprocessor::preprocess_html_checked`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreprocessOutcome {
+    /// Nothing is left after preprocessing
+    Empty(EmptyReason),
+    /// The preprocessed HTML
+    Content(String),
+}
+
 pub struct Preprocessor {
     config: PreprocessConfig,
+
+    /// Abbreviations already expanded during the current run
+    expanded_abbreviations: RefCell<HashSet<String>>,
+
+    /// Number of source nodes visited during the current run
+    visited_nodes: Cell<usize>,
+
+    /// Output nodes paired with the source node they derive from, recorded only while
+    /// building a map with [`Preprocessor::preprocess_html_with_map`]
+    origins: RefCell<Option<Vec<(Handle, Handle)>>>,
 }
 
 impl Preprocessor {
     pub fn new(config: PreprocessConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            expanded_abbreviations: RefCell::new(HashSet::new()),
+            visited_nodes: Cell::new(0),
+            origins: RefCell::new(None),
+        }
+    }
+
+    /// Record that the output node derives from the source `node`, if a map is being built
+    fn derived(&self, output: Handle, node: &Handle) -> Handle {
+        if let Some(origins) = self.origins.borrow_mut().as_mut() {
+            origins.push((output.clone(), node.clone()));
+        }
+        output
+    }
+
+    /// Get the tag name of an element after applying `rename_tags`
+    fn tag_name<'a>(&'a self, name: &'a QualName) -> &'a str {
+        self.config
+            .rename_tags
+            .get(name.local.as_ref())
+            .map(String::as_str)
+            .unwrap_or(name.local.as_ref())
+    }
+
+    /// Create a childless copy of the node, renaming elements according to `rename_tags`
+    fn shallow_copy(&self, node: &Handle) -> Handle {
+        match &node.data {
+            NodeData::Element {
+                name,
+                attrs,
+                template_contents,
+                mathml_annotation_xml_integration_point,
+            } => {
+                let mut name = name.clone();
+                if let Some(new_name) = self.config.rename_tags.get(name.local.as_ref()) {
+                    name.local = LocalName::from(new_name.as_str());
+                }
+                let mut attrs = attrs.borrow().clone();
+                if self.config.strip_attributes {
+                    attrs.retain(|attr| {
+                        self.config
+                            .semantic_attributes
+                            .contains(attr.name.local.as_ref())
+                    });
+                }
+                Node::new(NodeData::Element {
+                    name,
+                    attrs: RefCell::new(attrs),
+                    template_contents: RefCell::new(template_contents.borrow().clone()),
+                    mathml_annotation_xml_integration_point: *mathml_annotation_xml_integration_point,
+                })
+            }
+            _ => Node::new(NodeData::Document),
+        }
     }
 
     // Compact HTML to make it easier for LLMs to understand
@@ -78,115 +650,461 @@ impl Preprocessor {
     /// * Remove unnecessary nested elements
     /// * Compact text nodes
     pub fn preprocess_html(&self, html: &str) -> String {
-        if html.is_empty() {
-            return String::new();
+        match self.preprocess_html_checked(html) {
+            PreprocessOutcome::Content(output) => output,
+            PreprocessOutcome::Empty(_) => String::new(),
+        }
+    }
+
+    /// Like [`Preprocessor::preprocess_html`], but tells an empty input apart from an input
+    /// whose content was entirely removed
+    pub fn preprocess_html_checked(&self, html: &str) -> PreprocessOutcome {
+        if html.trim().is_empty() {
+            return PreprocessOutcome::Empty(EmptyReason::EmptyInput);
+        }
+
+        let doc = self.parse(html);
+
+        // The tree only holds text decoded from a `&str`, so the lossy conversion never
+        // replaces anything and keeps preprocessing infallible
+        match self.preprocess_tree(&doc.tree) {
+            Some(processed_tree) => {
+                PreprocessOutcome::Content(serialize_to_string_lossy(&processed_tree))
+            }
+            None => PreprocessOutcome::Empty(EmptyReason::AllRemoved),
         }
+    }
+
+    /// Like [`Preprocessor::preprocess_html`], but also returns the metadata of the page,
+    /// see [`extract_metadata`]
+    ///
+    /// The metadata is read before preprocessing removes the `<title>` and `<meta>` tags.
+    pub fn preprocess_with_metadata(&self, html: &str) -> (HashMap<String, String>, String) {
+        let doc = self.parse(html);
+        let metadata = extract_metadata(&doc.tree);
+        let output = self
+            .preprocess_tree(&doc.tree)
+            .map(|processed_tree| serialize_to_string_lossy(&processed_tree))
+            .unwrap_or_default();
+        (metadata, output)
+    }
+
+    /// Like [`Preprocessor::preprocess_html`], but also maps the output back to the source
+    ///
+    /// Each entry pairs the byte range of the output an element or text was written to, as
+    /// reported by [`serialize_with_ranges`], with the node of the parsed source it derives
+    /// from. A text merged from several source nodes maps to the element holding them, and
+    /// output built from scratch, like the text of a rendered table, has no entry.
+    ///
+    /// The parsed source is returned as well. Dropping it clears the children of every source
+    /// node, so it must be kept as long as the mapped nodes are used. Serializer errors are
+    /// returned like [`serialize_with_ranges`] does.
+    pub fn preprocess_html_with_map(&self, html: &str) -> io::Result<(String, NodeRanges, Dom)> {
+        let doc = self.parse(html);
+        self.origins.replace(Some(Vec::new()));
+        let processed = self.preprocess_tree(&doc.tree);
+
+        // Every recorded output node is still alive here, so no two share an address
+        let origins: HashMap<*const Node, Handle> = self
+            .origins
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(output, source)| (Rc::as_ptr(&output), source))
+            .collect();
+
+        let Some(processed) = processed else {
+            return Ok((String::new(), Vec::new(), doc));
+        };
+        let (output, ranges) = serialize_with_ranges(&processed)?;
+        let map = ranges
+            .into_iter()
+            .filter_map(|(range, node)| {
+                let source = origins.get(&Rc::as_ptr(&node))?;
+                Some((range, source.clone()))
+            })
+            .collect();
+        Ok((output, map, doc))
+    }
 
+    /// Parse the HTML the way the configuration expects it
+    fn parse(&self, html: &str) -> Dom {
         let opts = ParseOpts {
             tree_builder: TreeBuilderOpts {
                 drop_doctype: true,
+                scripting_enabled: !self.config.keep_noscript,
                 ..Default::default()
             },
             ..Default::default()
         };
-        let doc = parse_document(Dom::default(), opts)
+        parse_document(Dom::default(), opts)
             .from_utf8()
-            .one(html.as_bytes());
+            .one(html.as_bytes())
+    }
+
+    /// Preprocess an already parsed tree
+    ///
+    /// Returns a compacted copy of the tree, or `None` if nothing is left after preprocessing.
+    pub fn preprocess_tree(&self, tree: &Handle) -> Option<Handle> {
+        let mut processed = self.preprocess_node(tree);
+        self.expanded_abbreviations.borrow_mut().clear();
+        self.visited_nodes.set(0);
+
+        if self.config.collapse_empty_blocks
+            && let Some(tree) = processed.take()
+            && !collapse_empty_blocks(&tree)
+        {
+            processed = Some(tree);
+        }
+        processed
+    }
+
+    /// Render an `<abbr>` with its expansion if `expand_abbreviations` is set and this is the
+    /// first occurrence of the abbreviation
+    ///
+    /// Returns `None` if the element should be processed like any other element.
+    fn abbreviation_text(&self, node: &Handle) -> Option<String> {
+        if !self.config.expand_abbreviations {
+            return None;
+        }
+
+        let title = preprocess_text(&node.get_attribute("title")?);
+        let mut text = String::new();
+        collect_text(node, &mut text);
+        let text = preprocess_text(&text);
+        if title.is_empty() || text.is_empty() {
+            return None;
+        }
+
+        if !self.expanded_abbreviations.borrow_mut().insert(text.clone()) {
+            return None;
+        }
+        Some(format!("{} ({})", text, title))
+    }
+
+    /// Turn the `<caption>` of a removed table into a paragraph, if `keep_table_caption` is set
+    fn table_caption(&self, table: &Handle) -> Option<Handle> {
+        if !self.config.keep_table_caption {
+            return None;
+        }
+
+        let caption = table
+            .children
+            .borrow()
+            .iter()
+            .find(|child| {
+                matches!(&child.data, NodeData::Element { name, .. } if name.local.as_ref() == "caption")
+            })
+            .cloned()?;
+        let mut text = String::new();
+        collect_text(&caption, &mut text);
+        let text = preprocess_text(&text);
+        if text.is_empty() {
+            return None;
+        }
 
-        if let Some(processed_tree) = self.preprocess_node(&doc.tree) {
-            return serialize_to_string(&processed_tree);
+        let paragraph = Node::new_element("p", &[]);
+        let text = Node::new_text(text);
+        text.parent.set(Some(Rc::downgrade(&paragraph)));
+        paragraph.children.borrow_mut().push(text);
+        Some(paragraph)
+    }
+
+    /// Render a table as a `<pre>` with a Markdown or TSV grid according to `table_handling`
+    ///
+    /// Returns `None` if the table has no rows.
+    fn table_text(&self, table: &Handle) -> Option<Handle> {
+        let grid = table_grid(table, &|cell| {
+            let mut text = String::new();
+            collect_text(cell, &mut text);
+            preprocess_text(&text)
+        });
+        if grid.is_empty() {
+            return None;
         }
 
-        String::new()
+        let text = match self.config.table_handling {
+            TableHandling::Tsv => grid
+                .iter()
+                .map(|cells| cells.join("\t"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => pipe_table(&grid),
+        };
+
+        let pre = Node::new_element("pre", &[]);
+        let text = Node::new_text(text);
+        text.parent.set(Some(Rc::downgrade(&pre)));
+        pre.children.borrow_mut().push(text);
+        Some(pre)
+    }
+
+    /// Render a `<time>` element according to `time_handling`
+    ///
+    /// Returns `None` if the element should be processed like any other element.
+    fn time_text(&self, node: &Handle) -> Option<String> {
+        if self.config.time_handling == TimeHandling::Text {
+            return None;
+        }
+
+        let datetime = preprocess_text(&node.get_attribute("datetime")?);
+        if datetime.is_empty() {
+            return None;
+        }
+
+        let mut text = String::new();
+        collect_text(node, &mut text);
+        let text = preprocess_text(&text);
+
+        match self.config.time_handling {
+            TimeHandling::TextAndDatetime if !text.is_empty() => {
+                Some(format!("{} ({})", text, datetime))
+            }
+            _ => Some(datetime),
+        }
+    }
+
+    /// Render an `<a>` element as text according to `link_handling`
+    ///
+    /// Returns `None` if the link has no text.
+    fn link_text(&self, node: &Handle) -> Option<String> {
+        let mut text = String::new();
+        collect_text(node, &mut text);
+        let text = preprocess_text(&text);
+        if text.is_empty() {
+            return None;
+        }
+
+        let href = node.get_attribute("href").map(|href| href.trim().to_string());
+        let Some(href) = href.filter(|href| !href.is_empty()) else {
+            return Some(text);
+        };
+        match self.config.link_handling {
+            LinkHandling::Markdown => Some(format!("[{}]({})", text, href)),
+            _ => Some(format!("{} ({})", text, href)),
+        }
+    }
+
+    /// Whether `wrapper` can be replaced by its only processed child, according to
+    /// `collapse_single_child_wrappers`
+    fn is_collapsible_wrapper(&self, wrapper: &Handle, child: &Handle) -> bool {
+        let (NodeData::Element { name, attrs, .. }, NodeData::Element { name: child_name, .. }) =
+            (&wrapper.data, &child.data)
+        else {
+            return false;
+        };
+        let child_tag = child_name.local.as_ref();
+
+        self.config.collapse_single_child_wrappers
+            && BLOCK_TAGS.contains(&self.tag_name(name))
+            && BLOCK_TAGS.contains(&child_tag)
+            && !PARENT_BOUND_TAGS.contains(&child_tag)
+            && !attrs
+                .borrow()
+                .iter()
+                .any(|attr| self.config.semantic_attributes.contains(attr.name.local.as_ref()))
+    }
+
+    /// Whether the element is a transparent wrapper whose children replace it
+    fn is_transparent(&self, node: &Handle) -> bool {
+        self.config.keep_noscript
+            && matches!(&node.data, NodeData::Element { name, .. } if name.local.as_ref() == "noscript")
+    }
+
+    /// Get the children of the node, replacing transparent wrappers with their own children
+    fn child_nodes(&self, node: &Handle) -> Vec<Handle> {
+        let mut children = Vec::new();
+        for child in node.children.borrow().iter() {
+            if self.is_transparent(child) {
+                children.extend(self.child_nodes(child));
+            } else {
+                children.push(child.clone());
+            }
+        }
+        children
     }
 
     /// Recursively process the node and its children
     fn preprocess_node(&self, node: &Handle) -> Option<Handle> {
+        let visited = self.visited_nodes.get();
+        if self.config.max_nodes.is_some_and(|max| visited >= max) {
+            return None;
+        }
+        self.visited_nodes.set(visited + 1);
+
         // End conditions
         match &node.data {
             NodeData::Text { text } => {
-                let raw_text = text.borrow();
-                let processed_text = preprocess_text(&raw_text);
+                let mut text = self.config.emoji_policy.apply(&text.borrow());
+                if self.config.strip_invisible_controls {
+                    text.retain(|c| !is_invisible_control(c));
+                }
+                let normalized = NormalizedText::new(&text);
 
-                if processed_text.is_empty() {
+                if normalized.text.is_empty() {
                     return None;
                 }
 
-                return Some(Node::new_text(processed_text));
+                return Some(self.derived(Node::new_text(normalized.text), node));
             }
             NodeData::Element { name, .. } => {
-                if FORBIDDEN_TAGS.contains(&name.local.as_ref()) {
+                if self.is_transparent(node) {
+                    // Children are hoisted into the parent by `child_nodes`
                     return None;
                 }
-                if self.config.remove_links && name.local.as_ref() == "a" {
+                if name.local.as_ref() == "hr"
+                    && let Some(separator) = &self.config.thematic_break
+                {
+                    return Some(self.derived(Node::new_text(separator.clone()), node));
+                }
+                if self.config.forbidden_tags.contains(name.local.as_ref()) {
                     return None;
                 }
+                if name.local.as_ref() == "a" {
+                    match self.config.link_handling {
+                        LinkHandling::Keep => {}
+                        LinkHandling::Remove => return None,
+                        LinkHandling::Inline | LinkHandling::Markdown => {
+                            return self
+                                .link_text(node)
+                                .map(|text| self.derived(Node::new_text(text), node));
+                        }
+                    }
+                }
                 if self.config.remove_images && name.local.as_ref() == "img" {
                     return None;
                 }
-                if self.config.remove_tables && name.local.as_ref() == "table" {
-                    return None;
+                if name.local.as_ref() == "table" {
+                    match self.config.table_handling {
+                        TableHandling::Keep => {}
+                        TableHandling::Remove => return self.table_caption(node),
+                        TableHandling::Markdown | TableHandling::Tsv => {
+                            return self.table_text(node).map(|pre| self.derived(pre, node));
+                        }
+                    }
+                }
+                if name.local.as_ref() == "time"
+                    && let Some(text) = self.time_text(node)
+                {
+                    return Some(self.derived(Node::new_text(text), node));
+                }
+                if name.local.as_ref() == "abbr"
+                    && let Some(text) = self.abbreviation_text(node)
+                {
+                    return Some(self.derived(Node::new_text(text), node));
                 }
             }
             _ => {}
         }
 
-        let children = node.children.borrow().clone();
+        let children = self.child_nodes(node);
         let mut processed_children = Vec::new();
 
-        let mut texts = Vec::new();
+        // Adjacent texts are joined with a space only if the source had whitespace between them,
+        // so that e.g. `<b>re</b><i>act</i>` stays one word
+        let mut text = String::new();
+        let mut pending_space = false;
         let mut only_text = true;
+        // A text merged from several children derives from the node holding them
+        let mut text_source = node.clone();
+        let mut after_break = false;
         for child in children.iter() {
-            if let Some(processed) = self.preprocess_node(child) {
-                if let NodeData::Text { text: t } = &processed.data {
-                    texts.push(t.borrow().clone().to_string());
-                } else {
-                    only_text = false;
-                    if !texts.is_empty() {
-                        let combined_text = preprocess_text(&texts.join(" "));
-
-                        processed_children.push(Node::new_text(combined_text));
-                        texts.clear();
+            let is_break = self.config.thematic_break.is_some()
+                && matches!(
+                    &child.data,
+                    NodeData::Element { name, .. } if name.local.as_ref() == "hr"
+                );
+            // Only the first of consecutive `<hr>` is kept, ignoring blank text between them
+            if is_break && after_break {
+                continue;
+            }
+            // A separator never sticks to the words around it
+            let (leading_ws, trailing_ws) = if is_break {
+                (true, true)
+            } else {
+                edge_whitespace(child)
+            };
+            match self.preprocess_node(child) {
+                Some(processed) => {
+                    after_break = is_break;
+                    if let NodeData::Text { text: t } = &processed.data {
+                        if text.is_empty() {
+                            text_source = child.clone();
+                        } else {
+                            text_source = node.clone();
+                            if pending_space || leading_ws {
+                                text.push(' ');
+                            }
+                        }
+                        text.push_str(&t.borrow());
+                        pending_space = trailing_ws;
+                    } else {
+                        only_text = false;
+                        if !text.is_empty() {
+                            let run = Node::new_text(preprocess_text(&text));
+                            processed_children.push(self.derived(run, &text_source));
+                            text.clear();
+                        }
+                        pending_space = false;
+                        processed_children.push(processed);
+                    }
+                }
+                // Removed elements and whitespace-only text still separate words, except for
+                // comments and `<wbr>`, which may sit in the middle of a word
+                None => {
+                    let joins_words = match &child.data {
+                        NodeData::Comment { .. } => true,
+                        NodeData::Element { name, .. } => name.local.as_ref() == "wbr",
+                        _ => false,
+                    };
+                    if !joins_words {
+                        pending_space = true;
                     }
-                    processed_children.push(processed);
                 }
             }
         }
 
-        if !texts.is_empty() {
-            let combined_text = preprocess_text(&texts.join(" "));
-
-            processed_children.push(Node::new_text(combined_text));
+        if !text.is_empty() {
+            let run = Node::new_text(preprocess_text(&text));
+            processed_children.push(self.derived(run, &text_source));
         }
 
         // Compaction algorithm
 
-        // If the node has no children, return None
+        // If the node has no children, return None, unless it is a kept void element which
+        // never has any. `<wbr>` is still dropped so the words around it are joined
         if processed_children.is_empty() {
+            if let NodeData::Element { name, .. } = &node.data
+                && VOID_ELEMENTS.contains(&name.local.as_ref())
+                && name.local.as_ref() != "wbr"
+            {
+                return Some(self.derived(self.shallow_copy(node), node));
+            }
             return None;
         }
 
-        // If the number of children is 1 and the child is the same tag, skip the current node
-        if processed_children.len() == 1 {
-            let child = processed_children.first().expect("Child not found").clone();
+        // If the only child is an element with the same tag, skip the current node.
+        // Pending text has been flushed into `processed_children` at this point, so text
+        // before or after the child keeps the current node from being merged away.
+        if let [child] = processed_children.as_slice()
+            && let (NodeData::Element { name, .. }, NodeData::Element { name: child_name, .. }) =
+                (&node.data, &child.data)
+            && self.tag_name(name) == child_name.local.as_ref()
+        {
+            // The child was built by this run and has no parent yet, so it can be returned as
+            // is, keeping the source it was recorded with
+            return Some(child.clone());
+        }
 
-            match (&node.data, &child.data) {
-                (
-                    NodeData::Element { name, .. },
-                    NodeData::Element {
-                        name: child_name, ..
-                    },
-                ) if name.local.as_ref() == child_name.local.as_ref() => {
-                    // Create a deep copy of the child subtree to avoid reference issues
-                    return Some(child.deep_copy());
-                }
-                _ => {}
-            }
+        if let [child] = processed_children.as_slice()
+            && self.is_collapsible_wrapper(node, child)
+        {
+            return Some(child.clone());
         }
 
         // If the node is an inline element and only contains text nodes, compact the node
         if only_text
-            && matches!(&node.data, NodeData::Element { name, .. } if INLINE_TAGS.contains(&name.local.as_ref()))
+            && matches!(&node.data, NodeData::Element { name, .. } if self.config.inline_tags.contains(self.tag_name(name)))
         {
             let mut texts = Vec::new();
             for child in processed_children.iter() {
@@ -196,11 +1114,10 @@ impl Preprocessor {
             }
 
             let combined_text = preprocess_text(&texts.join(" "));
-            return Some(Node::new_text(combined_text));
+            return Some(self.derived(Node::new_text(combined_text), node));
         }
 
-        let new_node = node.clone();
-        new_node.parent.set(None);
+        let new_node = self.derived(self.shallow_copy(node), node);
 
         for child in processed_children.iter() {
             child.parent.set(Some(Rc::downgrade(&new_node)));
@@ -270,7 +1187,7 @@ mod tests {
         // - Inline tags (b, em, strong, i, code, small) converted to plain text
         // - blockquote converted to plain text since it only contains text
         // - Whitespace normalized
-        let expected = "<html><head><title>Test Page</title></head><body><div><p>This is a paragraph with bold text and emphasized text .</p><div><span>Regular span content</span></div></div><section><article><h1>Main heading</h1><p>Paragraph with strong text and italic text .</p><div>Quote with code snippet inside</div><ul><li>List item 1</li><li>List item with small text</li></ul></article></section></body></html>";
+        let expected = "<html><head><title>Test Page</title></head><body><div><p>This is a paragraph with bold text and emphasized text.</p><div><span>Regular span content</span></div></div><section><article><h1>Main heading</h1><p>Paragraph with strong text and italic text.</p><div>Quote with code snippet inside</div><ul><li>List item 1</li><li>List item with small text</li></ul></article></section></body></html>";
 
         assert_eq!(result, expected);
     }
@@ -279,9 +1196,10 @@ mod tests {
     fn test_remove_flags() {
         let html = "<div><p>Text with <a href='http://example.com'>link</a></p><img src='http://example.com/image.jpg' alt='Image' /></div>";
         let result = Preprocessor::new(PreprocessConfig {
-            remove_links: true,
+            link_handling: LinkHandling::Remove,
             remove_images: true,
-            remove_tables: true,
+            table_handling: TableHandling::Remove,
+            ..Default::default()
         })
         .preprocess_html(html);
         assert_eq!(
@@ -290,16 +1208,555 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preprocess_with_metadata() {
+        let html = r#"<html><head><title>Widgets</title>
+            <meta name="description" content="All about widgets">
+            <meta property="og:title" content="Widgets!"></head>
+            <body><p>Widgets are great.</p></body></html>"#;
+        let preprocessor = Preprocessor::new(PreprocessConfig::default());
+
+        let (metadata, output) = preprocessor.preprocess_with_metadata(html);
+        assert_eq!(output, preprocessor.preprocess_html(html));
+        assert_eq!(
+            output,
+            "<html><head><title>Widgets</title></head><body><p>Widgets are great.</p></body></html>"
+        );
+        assert_eq!(metadata["title"], "Widgets");
+        assert_eq!(metadata["description"], "All about widgets");
+        assert_eq!(metadata["og:title"], "Widgets!");
+
+        let (metadata, output) = preprocessor.preprocess_with_metadata("");
+        assert!(metadata.is_empty());
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_link_handling() {
+        let html = "<p>See <a href=' /docs '>the <b>docs</b></a>, <a name='top'>top</a> and \
+            <a href='/empty'> </a>now</p>";
+        let preprocess = |link_handling| {
+            Preprocessor::new(PreprocessConfig {
+                link_handling,
+                ..Default::default()
+            })
+            .preprocess_html(html)
+        };
+
+        assert_eq!(
+            preprocess(LinkHandling::Remove),
+            "<html><body><p>See , and now</p></body></html>"
+        );
+        assert_eq!(
+            preprocess(LinkHandling::Inline),
+            "<html><body><p>See the docs (/docs), top and now</p></body></html>"
+        );
+        assert_eq!(
+            preprocess(LinkHandling::Markdown),
+            "<html><body><p>See [the docs](/docs), top and now</p></body></html>"
+        );
+        assert_eq!(
+            preprocess(LinkHandling::Keep),
+            "<html><body><p>See<a href=\" /docs \">the docs</a>,<a name=\"top\">top</a>and now</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_rename_tags() {
+        let html = "<div><p><b>H<sub>2</sub>O</b></p><p>plain <span>span</span></p></div>";
+        let result = Preprocessor::new(PreprocessConfig {
+            rename_tags: HashMap::from([
+                ("b".to_string(), "strong".to_string()),
+                ("span".to_string(), "em".to_string()),
+            ]),
+            ..Default::default()
+        })
+        .preprocess_html(html);
+
+        // `b` is renamed to `strong`, and the renamed `em` is compacted as an inline tag
+        assert_eq!(
+            result,
+            "<html><body><div><p><strong>H<sub>2</sub>O</strong></p><p>plain span</p></div></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_custom_forbidden_and_inline_tags() {
+        let html = "<nav><p>Home <mark>here</mark></p></nav><aside><p>Side</p></aside>";
+        let preprocess = |config| Preprocessor::new(config).preprocess_html(html);
+
+        assert_eq!(
+            preprocess(PreprocessConfig::default()),
+            "<html><body><aside><p>Side</p></aside></body></html>"
+        );
+
+        let mut config = PreprocessConfig::default();
+        config.allow_tag("NAV").allow_tag("not-forbidden");
+        config.forbidden_tags.insert("aside".to_string());
+        config.inline_tags.insert("mark".to_string());
+        assert_eq!(
+            preprocess(config),
+            "<html><body><nav><p>Home here</p></nav></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_word_breaks() {
+        let preprocess =
+            |html| Preprocessor::new(PreprocessConfig::default()).preprocess_html(html);
+
+        assert_eq!(
+            preprocess("<p>encyclo<wbr>pedia article</p>"),
+            "<html><body><p>encyclopedia article</p></body></html>"
+        );
+        assert_eq!(
+            preprocess("<p>hy\u{00AD}phen\u{00AD}ated <b>te&shy;xt</b></p>"),
+            "<html><body><p>hyphenated text</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_strip_invisible_controls() {
+        let html = "<p>pay\u{200B}pal \u{FEFF}invoice\u{202E}fdp.exe\u{202C} wo\u{200D}rd</p>";
+        let preprocess = |strip_invisible_controls| {
+            Preprocessor::new(PreprocessConfig {
+                strip_invisible_controls,
+                ..Default::default()
+            })
+            .preprocess_html(html)
+        };
+
+        assert_eq!(
+            preprocess(true),
+            "<html><body><p>paypal invoicefdp.exe word</p></body></html>"
+        );
+        // Off by default
+        assert!(preprocess(false).contains("pay\u{200B}pal"));
+        assert!(preprocess(false).contains('\u{202E}'));
+    }
+
+    #[test]
+    fn test_keep_table_caption() {
+        let html = "<div><p>Intro</p><table><caption> Quarterly <b>sales</b> </caption>\
+            <tr><td>Q1</td><td>10</td></tr></table></div>";
+        let preprocess = |keep_table_caption| {
+            Preprocessor::new(PreprocessConfig {
+                keep_table_caption,
+                ..Default::default()
+            })
+            .preprocess_html(html)
+        };
+
+        assert_eq!(
+            preprocess(true),
+            "<html><body><div><p>Intro</p><p>Quarterly sales</p></div></body></html>"
+        );
+        assert_eq!(
+            preprocess(false),
+            "<html><body><div><p>Intro</p></div></body></html>"
+        );
+
+        // Tables that are kept are not changed
+        let result = Preprocessor::new(PreprocessConfig {
+            table_handling: TableHandling::Keep,
+            keep_table_caption: true,
+            ..Default::default()
+        })
+        .preprocess_html(html);
+        assert!(result.contains("<caption>Quarterly sales</caption>"));
+    }
+
+    #[test]
+    fn test_table_handling() {
+        let html = "<p>Prices</p><table><thead><tr><th>Item</th><th>Price</th></tr></thead>\
+            <tbody><tr><td>Tea | green</td><td>3 <b>EUR</b></td></tr></tbody></table>";
+        let preprocess = |table_handling| {
+            Preprocessor::new(PreprocessConfig {
+                table_handling,
+                ..Default::default()
+            })
+            .preprocess_html(html)
+        };
+
+        assert_eq!(
+            preprocess(TableHandling::Markdown),
+            "<html><body><p>Prices</p><pre>| Item | Price |\n| --- | --- |\n| Tea \\| green | 3 EUR |</pre></body></html>"
+        );
+        assert_eq!(
+            preprocess(TableHandling::Tsv),
+            "<html><body><p>Prices</p><pre>Item\tPrice\nTea | green\t3 EUR</pre></body></html>"
+        );
+        assert_eq!(
+            preprocess(TableHandling::Remove),
+            "<html><body><p>Prices</p></body></html>"
+        );
+        assert!(preprocess(TableHandling::Keep).contains("<th>Item</th>"));
+    }
+
+    #[test]
+    fn test_table_handling_ragged_rows() {
+        let html = "<table><tr><td>a</td><td colspan=2>b</td></tr><tr><td>c</td></tr>\
+            <tr></tr></table>";
+        let preprocess = |table_handling| {
+            Preprocessor::new(PreprocessConfig {
+                table_handling,
+                ..Default::default()
+            })
+            .preprocess_html(html)
+        };
+
+        // Without a `<thead>` the first row is the Markdown header
+        assert_eq!(
+            preprocess(TableHandling::Markdown),
+            "<html><body><pre>| a | b |  |\n| --- | --- | --- |\n| c |  |  |</pre></body></html>"
+        );
+        assert_eq!(
+            preprocess(TableHandling::Tsv),
+            "<html><body><pre>a\tb\t\nc\t\t</pre></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_strip_attributes() {
+        let html = r#"<div class="post" lang="fr" data-id="7"><p id="intro">Bonjour <time class="date" datetime="2024-01-02">hier</time></p></div>"#;
+        let preprocess = |strip_attributes| {
+            Preprocessor::new(PreprocessConfig {
+                strip_attributes,
+                ..Default::default()
+            })
+            .preprocess_html(html)
+        };
+
+        assert_eq!(
+            preprocess(true),
+            r#"<html><body><div lang="fr"><p>Bonjour<time datetime="2024-01-02">hier</time></p></div></body></html>"#
+        );
+        assert_eq!(
+            preprocess(false),
+            r#"<html><body><div class="post" lang="fr" data-id="7"><p id="intro">Bonjour<time class="date" datetime="2024-01-02">hier</time></p></div></body></html>"#
+        );
+    }
+
+    #[test]
+    fn test_time_handling() {
+        let html = r#"<p>Posted <time datetime="2024-01-02">Jan 2</time></p>"#;
+        let preprocess = |time_handling| {
+            Preprocessor::new(PreprocessConfig {
+                time_handling,
+                ..Default::default()
+            })
+            .preprocess_html(html)
+        };
+
+        assert_eq!(
+            preprocess(TimeHandling::Text),
+            r#"<html><body><p>Posted<time datetime="2024-01-02">Jan 2</time></p></body></html>"#
+        );
+        assert_eq!(
+            preprocess(TimeHandling::Datetime),
+            "<html><body><p>Posted 2024-01-02</p></body></html>"
+        );
+        assert_eq!(
+            preprocess(TimeHandling::TextAndDatetime),
+            "<html><body><p>Posted Jan 2 (2024-01-02)</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_keep_noscript() {
+        let html = "<div><p>App</p><noscript><p>Server rendered</p></noscript></div>";
+
+        let result = Preprocessor::new(PreprocessConfig::default()).preprocess_html(html);
+        assert_eq!(result, "<html><body><div><p>App</p></div></body></html>");
+
+        let result = Preprocessor::new(PreprocessConfig {
+            keep_noscript: true,
+            ..Default::default()
+        })
+        .preprocess_html(html);
+        assert_eq!(
+            result,
+            "<html><body><div><p>App</p><p>Server rendered</p></div></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_same_tag_merge_keeps_sibling_text() {
+        let preprocessor = Preprocessor::new(PreprocessConfig::default());
+
+        let result = preprocessor.preprocess_html("<div><div>inner</div> tail</div>");
+        assert_eq!(
+            result,
+            "<html><body><div><div>inner</div>tail</div></body></html>"
+        );
+
+        let result = preprocessor.preprocess_html("<div>head <div>inner</div></div>");
+        assert_eq!(
+            result,
+            "<html><body><div>head<div>inner</div></div></body></html>"
+        );
+
+        // Without sibling text the wrapper is still merged
+        let result = preprocessor.preprocess_html("<div><div>inner</div> <!-- c --></div>");
+        assert_eq!(result, "<html><body><div>inner</div></body></html>");
+    }
+
+    #[test]
+    fn test_preprocess_html_checked() {
+        let preprocessor = Preprocessor::new(PreprocessConfig::default());
+
+        for html in ["", "  \n "] {
+            assert_eq!(
+                preprocessor.preprocess_html_checked(html),
+                PreprocessOutcome::Empty(EmptyReason::EmptyInput)
+            );
+        }
+        assert_eq!(
+            preprocessor.preprocess_html_checked("<nav><a href=\"/\">Home</a></nav><script>x</script>"),
+            PreprocessOutcome::Empty(EmptyReason::AllRemoved)
+        );
+        assert_eq!(
+            preprocessor.preprocess_html_checked("<p>Text</p>"),
+            PreprocessOutcome::Content("<html><body><p>Text</p></body></html>".to_string())
+        );
+    }
+
+    #[test]
+    fn test_expand_abbreviations() {
+        let html = "<p><abbr title=\"HyperText Markup Language\">HTML</abbr> is parsed. \
+            <abbr title=\"HyperText Markup Language\">HTML</abbr> again.</p>";
+
+        let result = Preprocessor::new(PreprocessConfig::default()).preprocess_html(html);
+        assert!(!result.contains("HTML (HyperText Markup Language)"));
+
+        let preprocessor = Preprocessor::new(PreprocessConfig {
+            expand_abbreviations: true,
+            ..Default::default()
+        });
+        let expected = "<html><body><p>HTML (HyperText Markup Language) is parsed.\
+            <abbr title=\"HyperText Markup Language\">HTML</abbr>again.</p></body></html>";
+        assert_eq!(preprocessor.preprocess_html(html), expected);
+
+        // Each run starts over
+        assert_eq!(preprocessor.preprocess_html(html), expected);
+    }
+
+    #[test]
+    fn test_max_nodes() {
+        let html = "<div><p>One</p><p>Two</p><p>Three</p></div>";
+        let preprocessor = Preprocessor::new(PreprocessConfig {
+            max_nodes: Some(8),
+            ..Default::default()
+        });
+
+        // document, html, head, body, div, p, "One", p
+        let expected = "<html><body><div><p>One</p></div></body></html>";
+        assert_eq!(preprocessor.preprocess_html(html), expected);
+        assert_eq!(preprocessor.preprocess_html(html), expected);
+    }
+
+    #[test]
+    fn test_collapse_empty_blocks() {
+        let html = "<div><img src=\"a.png\"></div><div>real</div>\
+            <section><p>\u{200B}</p><div>\u{FEFF} </div></section>";
+
+        let result = Preprocessor::new(PreprocessConfig::default()).preprocess_html(html);
+        assert_eq!(
+            result,
+            "<html><body><div>real</div><section><p>\u{200B}</p><div>\u{FEFF}</div></section></body></html>"
+        );
+
+        let result = Preprocessor::new(PreprocessConfig {
+            collapse_empty_blocks: true,
+            ..Default::default()
+        })
+        .preprocess_html(html);
+        assert_eq!(result, "<html><body><div>real</div></body></html>");
+    }
+
+    #[test]
+    fn test_collapse_single_child_wrappers() {
+        let html = "<div class='outer'><section><div><article><p>Deep <b>text</b></p>\
+            </article></div></section></div>";
+        let preprocess = |html, collapse_single_child_wrappers| {
+            Preprocessor::new(PreprocessConfig {
+                collapse_single_child_wrappers,
+                ..Default::default()
+            })
+            .preprocess_html(html)
+        };
+
+        assert_eq!(
+            preprocess(html, true),
+            "<html><body><p>Deep text</p></body></html>"
+        );
+        assert_eq!(
+            preprocess(html, false),
+            "<html><body><div class=\"outer\"><section><div><article><p>Deep text</p></article></div></section></div></body></html>"
+        );
+
+        // Direct text, several children, semantic attributes and list items stop the collapse
+        assert_eq!(
+            preprocess(
+                "<div><section>Intro<p>a</p></section></div><div lang='fr'><p>b</p></div>\
+                <div><ul><li>c</li></ul></div>",
+                true
+            ),
+            "<html><body><section>Intro<p>a</p></section><div lang=\"fr\"><p>b</p></div><ul><li>c</li></ul></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_normalized_text() {
+        assert_eq!(
+            NormalizedText::new("  two\n  words "),
+            NormalizedText {
+                text: "two words".to_string(),
+                had_leading_ws: true,
+                had_trailing_ws: true,
+            }
+        );
+        assert_eq!(
+            NormalizedText::new("word\u{00A0}"),
+            NormalizedText {
+                text: "word".to_string(),
+                had_leading_ws: false,
+                had_trailing_ws: true,
+            }
+        );
+
+        let whitespace = NormalizedText::new(" \t ");
+        assert!(whitespace.text.is_empty());
+        assert!(whitespace.had_leading_ws && whitespace.had_trailing_ws);
+    }
+
+    #[test]
+    fn test_emoji_policy() {
+        let text = "Great 😀 news 🇺🇸! 👍🏽 👨\u{200D}👩\u{200D}👧 ❤\u{FE0F}";
+
+        assert_eq!(EmojiPolicy::Keep.apply(text), text);
+        assert_eq!(preprocess_text(&EmojiPolicy::Remove.apply(text)), "Great news !");
+        assert_eq!(
+            EmojiPolicy::ReplaceWithName.apply(text),
+            "Great :grinning: news :flag_us:! :thumbsup: 👨 :heart:"
+        );
+
+        let html = "<p>Launch 🚀 day</p>";
+        let result = Preprocessor::new(PreprocessConfig {
+            emoji_policy: EmojiPolicy::Remove,
+            ..Default::default()
+        })
+        .preprocess_html(html);
+        assert_eq!(result, "<html><body><p>Launch day</p></body></html>");
+    }
+
+    #[test]
+    fn test_inline_word_boundaries() {
+        let html = "<p><b>re</b><i>act</i></p><p><b>word</b>s and <b>two</b> <i>words</i></p>";
+        let result = Preprocessor::new(PreprocessConfig::default()).preprocess_html(html);
+        assert_eq!(
+            result,
+            "<html><body><p>react</p><p>words and two words</p></body></html>"
+        );
+
+        let html = "<p>Hello <b>big</b>world, <i>word</i> s<b> and </b>more&nbsp;<i>text</i></p>";
+        let result = Preprocessor::new(PreprocessConfig::default()).preprocess_html(html);
+        assert_eq!(
+            result,
+            "<html><body><p>Hello bigworld, word s and more text</p></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_preprocess_html_with_map() {
+        let html = "<div><p>Hello <b>big</b> world</p><p id='second'>Second paragraph</p></div>";
+        let preprocessor = Preprocessor::new(PreprocessConfig::default());
+        let (output, map, dom) = preprocessor.preprocess_html_with_map(html).unwrap();
+        assert_eq!(output, preprocessor.preprocess_html(html));
+
+        let source_of = |span: &str| {
+            map.iter()
+                .find(|(range, _)| &output[range.clone()] == span)
+                .map(|(_, source)| source.clone())
+                .unwrap()
+        };
+
+        // A text from a single source node maps to it
+        let source = source_of("Second paragraph");
+        assert!(
+            matches!(&source.data, NodeData::Text { text } if &**text.borrow() == "Second paragraph")
+        );
+        let source = source_of(r#"<p id="second">Second paragraph</p>"#);
+        assert_eq!(source.get_attribute("id").as_deref(), Some("second"));
+
+        // A text merged from several nodes maps to their parent
+        let source = source_of("Hello big world");
+        assert!(
+            matches!(&source.data, NodeData::Element { name, .. } if name.local.as_ref() == "p")
+        );
+
+        // Mapped nodes are still part of the returned source tree
+        assert_eq!(source.children.borrow().len(), 3);
+        assert_eq!(source.text_content(), "Hello big world");
+        assert!(source.is_descendant_of(&dom.tree));
+
+        let (output, map, _) = preprocessor.preprocess_html_with_map("<script>x</script>").unwrap();
+        assert!(output.is_empty() && map.is_empty());
+    }
+
+    #[test]
+    fn test_thematic_break() {
+        let html = "<p>a</p><hr><p>b</p><hr>\n<hr><hr><p>c</p>Tail<hr>end";
+        let result = Preprocessor::new(PreprocessConfig::default()).preprocess_html(html);
+        assert_eq!(result, "<html><body><p>a</p><p>b</p><p>c</p>Tail end</body></html>");
+
+        let result = Preprocessor::new(PreprocessConfig {
+            thematic_break: Some("---".to_string()),
+            ..Default::default()
+        })
+        .preprocess_html(html);
+        assert_eq!(
+            result,
+            "<html><body><p>a</p>---<p>b</p>---<p>c</p>Tail --- end</body></html>"
+        );
+    }
+
+    #[test]
+    fn test_keep_void_elements() {
+        let html = r#"<div><p>A cat</p><img src="cat.png" alt="Cat"><br><input></div>"#;
+        let result = Preprocessor::new(PreprocessConfig {
+            remove_images: false,
+            ..Default::default()
+        })
+        .preprocess_html(html);
+        assert_eq!(
+            result,
+            r#"<html><body><div><p>A cat</p><img src="cat.png" alt="Cat"><input></div></body></html>"#
+        );
+    }
+
     #[test]
     fn test_preprocess_text() {
         // Test HTML entity decoding
         let text_with_entities =
             "Hello&nbsp;world &amp; more&lt;test&gt; &quot;quotes&quot; &#39;apostrophe&#39;";
         let result = preprocess_text(text_with_entities);
+        assert_eq!(result, "Hello world & more<test> \"quotes\" 'apostrophe'");
+        // Hexadecimal references
+        assert_eq!(preprocess_text("&#x27;apostrophe&#X27;"), "'apostrophe'");
+
+        // Malformed and unknown references are left untouched
         assert_eq!(
-            result,
-            "Hello world &amp; more&lt;test&gt; &quot;quotes&quot; &#39;apostrophe&#39;"
+            preprocess_text("AT&T & co &amp &nosuch; &#; &#xZZ; &"),
+            "AT&T & co &amp &nosuch; &#; &#xZZ; &"
+        );
+        // Multi-character, C1 and out of range references
+        assert_eq!(
+            preprocess_text("&NotEqualTilde; &#x80; &#150; &#0; &#x110000; &#128512;"),
+            "\u{2242}\u{338} \u{20AC} \u{2013} \u{FFFD} \u{FFFD} \u{1F600}"
         );
+        assert_eq!(preprocess_text("&CounterClockwiseContourIntegral;"), "\u{2233}");
+        assert_eq!(preprocess_text("de&shy;cod&#173;ed"), "decoded");
 
         // Test whitespace normalization
         let text_with_whitespace = "  Hello\n\tworld  \r\n  with   lots    of     spaces  ";
@@ -1,6 +1,10 @@
 use std::collections::HashSet;
 
 use ammonia::Builder;
+use html5ever::tendril::StrTendril;
+
+use crate::node::{Handle, NodeData, serialize_to_string_lossy};
+use crate::parse::parse_body_fragment;
 
 /// All HTML tags
 const HTML_TAGS: &[&str] = &[
@@ -156,6 +160,16 @@ const ALLOWED_ATTRS: &[&str] = &["class", "id", "title", "lang"];
 /// Default tags that are removed
 const BLACKLISTED_TAGS: &[&str] = &["script", "style"];
 
+/// What happens to attribute values longer than [`SanitizeOptions::max_attribute_value_len`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OversizedAttribute {
+    /// Remove the attribute
+    #[default]
+    Drop,
+    /// Cut the value down to the limit, at a character boundary
+    Truncate,
+}
+
 /// Sanitize options
 #[derive(Default)]
 pub struct SanitizeOptions {
@@ -166,6 +180,22 @@ pub struct SanitizeOptions {
     pub remove_links: bool,
     pub remove_images: bool,
     pub remove_tables: bool,
+
+    /// Rewrite duplicate `id` attributes by appending a suffix (`-2`, `-3`, ...).
+    ///
+    /// The first element with a given id keeps it. References such as `href="#id"` are not
+    /// updated, so they keep pointing at that first element, as they would in a browser.
+    pub dedupe_ids: bool,
+
+    /// Limit the length of attribute values, in bytes.
+    ///
+    /// A hardening measure against huge values, such as giant `data:` URIs or `style` blobs,
+    /// that ammonia keeps as they are. Oversized values are handled according to
+    /// `oversized_attribute`. Unlimited by default.
+    pub max_attribute_value_len: Option<usize>,
+
+    /// Whether attributes over `max_attribute_value_len` are dropped or truncated
+    pub oversized_attribute: OversizedAttribute,
 }
 
 
@@ -219,14 +249,103 @@ pub fn sanitize_html(html: &str, options: &SanitizeOptions) -> String {
         tag_specific_attributes.remove(tag);
     }
 
-    builder
+    let sanitized = builder
         .add_tags(allowed_tags)
         .add_generic_attributes(allowed_attributes)
         .tag_attributes(tag_specific_attributes)
         .clean_content_tags(blacklisted_tags)
         .link_rel(None)
         .clean(html)
-        .to_string()
+        .to_string();
+
+    if !options.dedupe_ids && options.max_attribute_value_len.is_none() {
+        return sanitized;
+    }
+    post_process(&sanitized, options)
+}
+
+/// Apply the options ammonia doesn't support to the sanitized HTML
+fn post_process(html: &str, options: &SanitizeOptions) -> String {
+    let dom = parse_body_fragment(html);
+    let Some(root) = dom.tree.children.borrow().first().cloned() else {
+        return String::new();
+    };
+
+    if options.dedupe_ids {
+        dedupe_ids(&root);
+    }
+    if let Some(max_len) = options.max_attribute_value_len {
+        limit_attribute_values(&root, max_len, options.oversized_attribute);
+    }
+    serialize_to_string_lossy(&root)
+}
+
+/// Drop or truncate the attribute values below `node` that are longer than `max_len` bytes
+fn limit_attribute_values(node: &Handle, max_len: usize, oversized: OversizedAttribute) {
+    if let NodeData::Element { attrs, .. } = &node.data {
+        let mut attrs = attrs.borrow_mut();
+        match oversized {
+            OversizedAttribute::Drop => attrs.retain(|attr| attr.value.len() <= max_len),
+            OversizedAttribute::Truncate => {
+                for attr in attrs.iter_mut().filter(|attr| attr.value.len() > max_len) {
+                    let mut end = max_len;
+                    while !attr.value.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    attr.value = StrTendril::from_slice(&attr.value[..end]);
+                }
+            }
+        }
+    }
+    for child in node.children.borrow().iter() {
+        limit_attribute_values(child, max_len, oversized);
+    }
+}
+
+/// Rename every repeated `id` below `root` so that ids are unique
+fn dedupe_ids(root: &Handle) {
+    let mut elements = Vec::new();
+    collect_elements_with_id(root, &mut elements);
+
+    // Every id in the fragment, so that generated ids never collide with existing ones
+    let mut taken: HashSet<String> = elements.iter().map(|(_, id)| id.clone()).collect();
+    let mut seen = HashSet::new();
+    for (element, id) in elements {
+        if seen.insert(id.clone()) {
+            continue;
+        }
+
+        let mut suffix = 2;
+        let mut new_id = format!("{}-{}", id, suffix);
+        while taken.contains(&new_id) {
+            suffix += 1;
+            new_id = format!("{}-{}", id, suffix);
+        }
+
+        if let NodeData::Element { attrs, .. } = &element.data {
+            for attr in attrs.borrow_mut().iter_mut() {
+                if attr.name.local.as_ref() == "id" {
+                    attr.value = StrTendril::from_slice(&new_id);
+                }
+            }
+        }
+        taken.insert(new_id);
+    }
+}
+
+/// Collect the elements that have an `id`, in document order
+fn collect_elements_with_id(node: &Handle, elements: &mut Vec<(Handle, String)>) {
+    for child in node.children.borrow().iter() {
+        if let NodeData::Element { attrs, .. } = &child.data
+            && let Some(id) = attrs
+                .borrow()
+                .iter()
+                .find(|attr| attr.name.local.as_ref() == "id")
+        {
+            elements.push((child.clone(), id.value.to_string()));
+        }
+        collect_elements_with_id(child, elements);
+    }
 }
 
 #[cfg(test)]
@@ -285,4 +404,52 @@ mod tests {
         );
         assert_eq!(sanitized, "<p>Text with </p>");
     }
+
+    #[test]
+    fn test_sanitize_html_max_attribute_value_len() {
+        let html = "<p title=\"short\" lang=\"en\">A</p><p title=\"a very long title\">B</p>\
+            <p title=\"abcd\u{e9}\">C</p>";
+        let sanitize = |oversized_attribute| {
+            sanitize_html(
+                html,
+                &SanitizeOptions {
+                    max_attribute_value_len: Some(5),
+                    oversized_attribute,
+                    ..Default::default()
+                },
+            )
+        };
+
+        assert_eq!(
+            sanitize(OversizedAttribute::Drop),
+            "<p title=\"short\" lang=\"en\">A</p><p>B</p><p>C</p>"
+        );
+        // "é" takes two bytes, so it doesn't fit in the last byte
+        assert_eq!(
+            sanitize(OversizedAttribute::Truncate),
+            "<p title=\"short\" lang=\"en\">A</p><p title=\"a ver\">B</p><p title=\"abcd\">C</p>"
+        );
+    }
+
+    #[test]
+    fn test_sanitize_html_dedupe_ids() {
+        let html = "<h2 id=\"intro\">A</h2><p id=\"intro-2\">B</p>\
+            <h2 id=\"intro\">C</h2><div><h2 id=\"intro\">D</h2></div><a href=\"#intro\">Top</a>";
+
+        let sanitized = sanitize_html(html, &SanitizeOptions::default());
+        assert_eq!(sanitized.matches("id=\"intro\"").count(), 3);
+
+        let sanitized = sanitize_html(
+            html,
+            &SanitizeOptions {
+                dedupe_ids: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            sanitized,
+            "<h2 id=\"intro\">A</h2><p id=\"intro-2\">B</p>\
+            <h2 id=\"intro-3\">C</h2><div><h2 id=\"intro-4\">D</h2></div><a href=\"#intro\">Top</a>"
+        );
+    }
 }
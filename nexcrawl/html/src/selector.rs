@@ -1,12 +1,425 @@
-use crate::node::{Handle, NodeData};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+use html5ever::{Namespace, ns};
+use html5ever::interface::QuirksMode;
+
+use crate::node::{
+    Dom, Handle, Node, NodeData, outermost, parent_and_index, remove_from_parent,
+};
+
+/// How the value of an attribute selector is compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AttrMatch {
+    /// `[name=value]`: the value is exactly `value`
+    Exact,
+    /// `[name~=value]`: the value is a whitespace-separated list containing `value`
+    ContainsWord,
+    /// `[name^=value]`: the value starts with `value`
+    Prefix,
+    /// `[name$=value]`: the value ends with `value`
+    Suffix,
+    /// `[name*=value]`: the value contains `value`
+    Substring,
+}
 
-/// Represents a single segment of a selector (e.g., "div.class#id")
+/// How a segment relates to the segment before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Combinator {
+    /// `a b`: the element is a descendant of the previous match
+    #[default]
+    Descendant,
+    /// `a > b`: the element is a child of the previous match
+    Child,
+}
+
+/// A pseudo-class that filters elements by their position or state
 #[derive(Debug, Clone, PartialEq)]
+pub(crate) enum PseudoClass {
+    /// `:first-of-type`: the first sibling element with the same tag name
+    FirstOfType,
+    /// `:last-of-type`: the last sibling element with the same tag name
+    LastOfType,
+    /// `:scope`: the scope element of a relative query, see [`Selector::select_scoped`]
+    Scope,
+    /// `:root`: the document element, i.e. the element whose parent is the `Document`
+    /// (`<html>` in a parsed page), or the root element of a detached tree
+    Root,
+    /// `:first-child`: the first element among its siblings
+    FirstChild,
+    /// `:last-child`: the last element among its siblings
+    LastChild,
+    /// `:nth-child(an+b)`: the element's 1-based position among its sibling elements is
+    /// `a * n + b` for some `n >= 0`
+    NthChild { a: i32, b: i32 },
+    /// `:not(segment)`: the element does not match the compound segment, e.g. `:not(.ad)`
+    Not(Box<SelectorSegment>),
+    /// `:has(selector)`: a descendant of the element matches the relative selector, e.g.
+    /// `:has(img)` or `:has(> a)`. The segments start with a `:scope` segment standing for
+    /// the element itself.
+    Has(Vec<SelectorSegment>),
+}
+
+/// The element name of the universal selector `*`, which matches any element
+const UNIVERSAL: &str = "*";
+
+/// Represents a single segment of a selector (e.g., "div.class#id")
+#[derive(Debug, Clone, PartialEq, Default)]
 #[cfg_attr(test, derive())]
 pub(crate) struct SelectorSegment {
     element: Option<String>,
+    /// The namespace of a prefixed element selector such as `svg|circle`
+    namespace: Option<Namespace>,
     classes: Vec<String>,
     id: Option<String>,
+    /// Attribute selectors as (name, value, match); a `None` value only checks presence
+    attributes: Vec<(String, Option<String>, AttrMatch)>,
+    pseudo_classes: Vec<PseudoClass>,
+    /// The relation to the previous segment; ignored for the first segment
+    combinator: Combinator,
+}
+
+/// Errors returned when parsing a malformed selector
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelectorError {
+    /// The selector is empty or only contains whitespace
+    Empty,
+    /// An attribute selector is missing its closing bracket, e.g. `a[href`
+    UnclosedBracket,
+    /// A pseudo-class argument is missing its closing parenthesis, e.g. `li:nth-child(2`
+    UnclosedParenthesis,
+    /// An attribute selector has no attribute name, e.g. `[=value]`
+    MissingAttributeName,
+    /// A token that is not valid at its position, e.g. a stray `]`, text directly after an
+    /// attribute selector (`[href]a`), or an unquoted value containing whitespace
+    UnexpectedToken(String),
+    /// A pseudo-class that is not supported, e.g. `:hover`
+    UnknownPseudo(String),
+    /// A namespace prefix that is not supported, e.g. `xlink|href`
+    UnknownNamespace(String),
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectorError::Empty => write!(f, "empty selector"),
+            SelectorError::UnclosedBracket => write!(f, "unclosed attribute selector bracket"),
+            SelectorError::UnclosedParenthesis => {
+                write!(f, "unclosed pseudo-class parenthesis")
+            }
+            SelectorError::MissingAttributeName => {
+                write!(f, "attribute selector without attribute name")
+            }
+            SelectorError::UnexpectedToken(token) => write!(f, "unexpected token: {}", token),
+            SelectorError::UnknownPseudo(name) => write!(f, "unknown pseudo-class: :{}", name),
+            SelectorError::UnknownNamespace(prefix) => {
+                write!(f, "unknown namespace prefix: {}|", prefix)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SelectorError {}
+
+/// Options that change how selectors are matched
+#[derive(Debug, Clone)]
+pub struct SelectorOptions {
+    /// The quirks mode of the document being queried.
+    ///
+    /// Browsers match `#id` and `.class` selectors case-insensitively in quirks mode.
+    /// Matching is case-sensitive unless this is set to [`QuirksMode::Quirks`],
+    /// e.g. with [`SelectorOptions::for_dom`].
+    pub quirks_mode: QuirksMode,
+
+    /// The default namespace of type selectors, like CSS `@namespace`.
+    ///
+    /// When set, element selectors such as `circle` only match elements in this namespace
+    /// (e.g. `ns!(svg)`). By default they match elements in any namespace. Selectors with a
+    /// namespace prefix, like `svg|circle`, ignore it.
+    pub default_namespace: Option<Namespace>,
+
+    /// Compare id, class and attribute values ASCII case-insensitively.
+    ///
+    /// Useful for sites that are inconsistent about the casing of their ids and classes.
+    /// Attribute names are always matched case-insensitively.
+    pub case_insensitive_values: bool,
+
+    /// Stop matching after visiting this many nodes.
+    ///
+    /// A safeguard against adversarial input: once the budget is exhausted, the matches found
+    /// so far are returned instead of an error. The nodes searched by `:has(...)` count
+    /// against the same budget. Unlimited by default.
+    pub max_nodes: Option<usize>,
+
+    /// Treat `<slot>` elements as transparent when matching combinators.
+    ///
+    /// Component markup projects content through `<slot>`, so in the rendered page slotted
+    /// elements appear as children of the slot's parent. With this set, `section > p` matches
+    /// a `<p>` inside a `<slot>` inside a `<section>`. This is a heuristic: shadow roots are
+    /// not rendered, so content is only found where it sits in the markup, and the contents
+    /// of `<template>` elements are never searched. `<slot>` elements can still be matched
+    /// themselves. Off by default.
+    pub transparent_slots: bool,
+}
+
+impl Default for SelectorOptions {
+    fn default() -> Self {
+        Self {
+            quirks_mode: QuirksMode::NoQuirks,
+            default_namespace: None,
+            case_insensitive_values: false,
+            max_nodes: None,
+            transparent_slots: false,
+        }
+    }
+}
+
+impl SelectorOptions {
+    /// Options mirroring how a browser would match selectors against the document
+    pub fn for_dom(dom: &Dom) -> Self {
+        Self {
+            quirks_mode: dom.quirks_mode.get(),
+            ..Default::default()
+        }
+    }
+
+    /// The number of node visits allowed by `max_nodes`
+    fn node_budget(&self) -> usize {
+        self.max_nodes.unwrap_or(usize::MAX)
+    }
+
+    /// Whether `#id` and `.class` are matched case-insensitively
+    fn ignore_id_class_case(&self) -> bool {
+        self.quirks_mode == QuirksMode::Quirks || self.case_insensitive_values
+    }
+}
+
+/// A parsed selector that can be matched against many trees
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    /// The comma-separated selectors of the group, each as a chain of segments
+    groups: Vec<Vec<SelectorSegment>>,
+}
+
+impl Selector {
+    /// Parse a selector string
+    ///
+    /// See [`select`] for the supported syntax and [`SelectorError`] for the error cases.
+    pub fn parse(selector: &str) -> Result<Self, SelectorError> {
+        Ok(Self {
+            groups: parse_selector_private(selector)?,
+        })
+    }
+
+    /// The CSS specificity of the selector as (ids, classes, types)
+    ///
+    /// Classes, attribute selectors and pseudo-classes count as classes, and `*` counts as
+    /// nothing. Like CSS, `:not(...)` and `:has(...)` count as their argument. Tuples compare in cascade order,
+    /// so the greater specificity wins. For a group like `h1, .title`, this is the highest
+    /// specificity of its selectors.
+    pub fn specificity(&self) -> (u32, u32, u32) {
+        self.groups
+            .iter()
+            .map(|segments| {
+                segments.iter().fold((0, 0, 0), |(a, b, c), segment| {
+                    let (ids, classes, types) = segment_specificity(segment);
+                    (a + ids, b + classes, c + types)
+                })
+            })
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Select all matching nodes in the tree
+    pub fn select(&self, tree: &Handle) -> Vec<Handle> {
+        self.select_with_options(tree, &SelectorOptions::default())
+    }
+
+    /// Select all matching nodes in the tree using the given options
+    pub fn select_with_options(&self, tree: &Handle, options: &SelectorOptions) -> Vec<Handle> {
+        let mut results = Vec::new();
+        self.for_each_match(tree, options, |node| results.push(node.clone()));
+        results
+    }
+
+    /// Select the first matching node in the tree, in document order
+    ///
+    /// Same as `self.select(tree).first()`, but the traversal stops at the first match.
+    pub fn select_first(&self, tree: &Handle) -> Option<Handle> {
+        let options = SelectorOptions::default();
+        let budget = Cell::new(options.node_budget());
+        if let Some(fast_path) = self.fast_path(&options) {
+            return select_first_fast(tree, &fast_path, &options, &budget);
+        }
+        let context = MatchContext {
+            options: &options,
+            root: Some(tree),
+            scope: None,
+            budget: &budget,
+        };
+        select_first_recursive(tree, &self.groups, &context)
+    }
+
+    /// Visit the matching nodes of the tree in document order
+    fn for_each_match<F: FnMut(&Handle)>(
+        &self,
+        tree: &Handle,
+        options: &SelectorOptions,
+        mut on_match: F,
+    ) {
+        let budget = Cell::new(options.node_budget());
+        if let Some(fast_path) = self.fast_path(options) {
+            select_all_fast(tree, &fast_path, options, &budget, &mut on_match);
+            return;
+        }
+        let context = MatchContext {
+            options,
+            root: Some(tree),
+            scope: None,
+            budget: &budget,
+        };
+        select_all_recursive(tree, &self.groups, &context, &mut on_match);
+    }
+
+    /// Get the fast path of a selector that is a lone id or tag name, like `#main` or `p`
+    ///
+    /// These skip the segment matching of the general path and return the same nodes in the
+    /// same order. There is no id index, so `#id` still visits the whole tree.
+    fn fast_path(&self, options: &SelectorOptions) -> Option<FastPath<'_>> {
+        let [segments] = self.groups.as_slice() else {
+            return None;
+        };
+        let [segment] = segments.as_slice() else {
+            return None;
+        };
+        if !segment.classes.is_empty()
+            || !segment.attributes.is_empty()
+            || !segment.pseudo_classes.is_empty()
+            || segment.namespace.is_some()
+        {
+            return None;
+        }
+        match (&segment.element, &segment.id) {
+            (None, Some(id)) => Some(FastPath::Id(id)),
+            (Some(tag), None) if tag != UNIVERSAL && options.default_namespace.is_none() => {
+                Some(FastPath::Tag(tag))
+            }
+            _ => None,
+        }
+    }
+
+    /// Select the descendants of `scope` matching this selector, relative to `scope`
+    ///
+    /// `:scope` matches `scope` itself, so e.g. `:scope > .child` selects the direct children
+    /// of `scope` with the class `child`. Ancestors outside of `scope` are never considered,
+    /// and `scope` itself is not part of the results.
+    ///
+    /// `:scope` only makes sense in relative queries: [`select`] and the other tree-wide
+    /// queries have no scope element, so `:scope` never matches there.
+    pub fn select_scoped(&self, scope: &Handle) -> Vec<Handle> {
+        self.select_scoped_with_options(scope, &SelectorOptions::default())
+    }
+
+    /// Like [`Selector::select_scoped`], using the given options
+    pub fn select_scoped_with_options(
+        &self,
+        scope: &Handle,
+        options: &SelectorOptions,
+    ) -> Vec<Handle> {
+        let budget = Cell::new(options.node_budget());
+        let context = MatchContext {
+            options,
+            root: Some(scope),
+            scope: Some(scope),
+            budget: &budget,
+        };
+        let mut results = Vec::new();
+        for child in scope.children.borrow().iter() {
+            select_all_recursive(child, &self.groups, &context, &mut |node| {
+                results.push(node.clone())
+            });
+        }
+        results
+    }
+
+    /// Check whether a node matches this selector
+    ///
+    /// Combinators are checked against the node's whole ancestor chain. `:scope` never matches.
+    pub fn matches(&self, node: &Handle) -> bool {
+        let options = SelectorOptions::default();
+        let budget = Cell::new(options.node_budget());
+        let context = MatchContext {
+            options: &options,
+            root: None,
+            scope: None,
+            budget: &budget,
+        };
+        matches_any_group(node, &self.groups, &context)
+    }
+
+    /// Check whether a node matches this selector relative to `scope`
+    ///
+    /// `:scope` matches `scope`, and ancestors outside of `scope` are never considered,
+    /// the same way as in [`Selector::select_scoped`].
+    pub fn matches_scoped(&self, node: &Handle, scope: &Handle) -> bool {
+        let options = SelectorOptions::default();
+        let budget = Cell::new(options.node_budget());
+        let context = MatchContext {
+            options: &options,
+            root: Some(scope),
+            scope: Some(scope),
+            budget: &budget,
+        };
+        (node.is_descendant_of(scope) || node.same_node(scope))
+            && matches_any_group(node, &self.groups, &context)
+    }
+
+    /// Count the matching nodes in the tree without collecting them
+    pub fn count(&self, tree: &Handle) -> usize {
+        self.count_with_options(tree, &SelectorOptions::default())
+    }
+
+    /// Count the matching nodes in the tree using the given options
+    pub fn count_with_options(&self, tree: &Handle, options: &SelectorOptions) -> usize {
+        let mut count = 0;
+        self.for_each_match(tree, options, |_| count += 1);
+        count
+    }
+}
+
+/// The specificity of a single compound segment as (ids, classes, types)
+fn segment_specificity(segment: &SelectorSegment) -> (u32, u32, u32) {
+    let mut specificity = (
+        segment.id.is_some() as u32,
+        (segment.classes.len() + segment.attributes.len()) as u32,
+        segment
+            .element
+            .as_ref()
+            .is_some_and(|element| element != UNIVERSAL) as u32,
+    );
+    for pseudo_class in &segment.pseudo_classes {
+        match pseudo_class {
+            PseudoClass::Not(inner) => {
+                let (ids, classes, types) = segment_specificity(inner);
+                specificity.0 += ids;
+                specificity.1 += classes;
+                specificity.2 += types;
+            }
+            // Without the leading `:scope` segment
+            PseudoClass::Has(segments) => {
+                for inner in &segments[1..] {
+                    let (ids, classes, types) = segment_specificity(inner);
+                    specificity.0 += ids;
+                    specificity.1 += classes;
+                    specificity.2 += types;
+                }
+            }
+            _ => specificity.1 += 1,
+        }
+    }
+    specificity
 }
 
 /// Select all matching nodes in the tree
@@ -14,13 +427,27 @@ pub(crate) struct SelectorSegment {
 /// CSS-like selector utility for querying DOM nodes.
 ///
 /// Supports:
-/// - Element selectors: "div", "span", "p"
+/// - Element selectors: "div", "span", "p", and "*" for any element
+/// - Namespaced element selectors: "svg|circle", "math|mi", "html|a", "svg|*"
 /// - Class selectors: ".className"
 /// - ID selectors: "#idName"
+/// - Attribute selectors: "[href]", "[type=text]", "[rel~=nofollow]", "[href^=https]",
+///   "[src$='.png']", "[title*=news]"
+/// - Pseudo-classes: ":first-of-type", ":last-of-type", ":root", ":first-child",
+///   ":last-child", ":nth-child(2)", ":nth-child(2n+1)", ":nth-child(odd)"
+/// - Negation of a compound selector: "div:not(.ad)", "a:not([rel~=nofollow].external)"
+/// - Elements with a matching descendant: "div:has(img)", "li:has(> a)", "ul:has(li .price)"
 /// - Combined selectors: "div.className#id"
 /// - Descendant selectors: "div span.active"
+/// - Child selectors: "ul > li"
+/// - Selector groups: "h1, h2, .title", matching any of the selectors
+///
+/// Matches are returned once each, in document order. The tree root itself can match, and
+/// combinators only consider ancestors up to the tree root. `:scope` is only meaningful in
+/// relative queries such as [`Selector::select_scoped`] and never matches here.
 ///
 /// Returns a vector of all matching nodes, or an empty vector if no matches are found.
+/// A malformed selector matches nothing; use [`try_select`] to get the parse error.
 ///
 /// # Examples
 ///
@@ -32,130 +459,860 @@ pub(crate) struct SelectorSegment {
 /// let results = select(&root, "div.item");
 /// ```
 pub fn select(tree: &Handle, selector: &str) -> Vec<Handle> {
-    if selector.trim().is_empty() {
-        return Vec::new();
+    select_with_options(tree, selector, &SelectorOptions::default())
+}
+
+/// Select all matching nodes in the tree, reporting a malformed selector
+///
+/// Same as [`select`], but returns the [`SelectorError`] instead of no matches, so that a
+/// mistake in the selector can't pass for a page without matches.
+///
+/// # Examples
+///
+/// ```
+/// use nexcrawl_html::{SelectorError, parse_html, try_select};
+///
+/// let dom = parse_html("<p>Hello</p>");
+/// assert_eq!(try_select(&dom.tree, "p").unwrap().len(), 1);
+/// assert_eq!(try_select(&dom.tree, "p[lang"), Err(SelectorError::UnclosedBracket));
+/// ```
+pub fn try_select(tree: &Handle, selector: &str) -> Result<Vec<Handle>, SelectorError> {
+    Ok(Selector::parse(selector)?.select(tree))
+}
+
+/// Select all matching nodes in the tree using the given options
+///
+/// # Examples
+///
+/// ```
+/// use nexcrawl_html::{parse_html, select_with_options, SelectorOptions};
+///
+/// // Without a doctype the document is in quirks mode
+/// let dom = parse_html("<div class=\"Item\"></div>");
+/// let results = select_with_options(&dom.tree, ".item", &SelectorOptions::for_dom(&dom));
+/// assert_eq!(results.len(), 1);
+/// ```
+pub fn select_with_options(
+    tree: &Handle,
+    selector: &str,
+    options: &SelectorOptions,
+) -> Vec<Handle> {
+    match Selector::parse(selector) {
+        Ok(selector) => selector.select_with_options(tree, options),
+        Err(_) => Vec::new(),
     }
+}
 
-    let segments = parse_selector_private(selector);
+/// Select the first matching node in the tree, in document order
+///
+/// Same as `select(tree, selector).first()`, but stops walking the tree at the first match.
+/// Returns `None` if nothing matches or the selector is malformed.
+pub fn select_first(tree: &Handle, selector: &str) -> Option<Handle> {
+    Selector::parse(selector).ok()?.select_first(tree)
+}
 
-    if segments.is_empty() {
+/// Select all matching nodes in several trees, e.g. the roots of a parsed fragment
+///
+/// The results of each root are concatenated in the order of `roots`, each in document order.
+/// A node reachable from several roots (e.g. a root nested in another one) is only returned
+/// the first time it is found.
+pub fn select_in(roots: &[Handle], selector: &str) -> Vec<Handle> {
+    let Ok(selector) = Selector::parse(selector) else {
         return Vec::new();
+    };
+
+    let mut seen = HashSet::new();
+    roots
+        .iter()
+        .flat_map(|root| selector.select(root))
+        .filter(|node| seen.insert(Rc::as_ptr(node)))
+        .collect()
+}
+
+/// Select the parents of all matching nodes in the tree
+///
+/// E.g. `select_parents(tree, "a")` returns the elements containing links, such as their
+/// `<li>`s. Each parent is returned once, in the order of its first matching child. Matches
+/// without a parent are skipped.
+pub fn select_parents(tree: &Handle, selector: &str) -> Vec<Handle> {
+    let mut seen = HashSet::new();
+    select(tree, selector)
+        .iter()
+        .filter_map(|node| parent_and_index(node).map(|(parent, _)| parent))
+        .filter(|parent| seen.insert(Rc::as_ptr(parent)))
+        .collect()
+}
+
+/// Count all matching nodes in the tree
+///
+/// Equivalent to `select(tree, selector).len()` without allocating the results.
+pub fn select_count(tree: &Handle, selector: &str) -> usize {
+    match Selector::parse(selector) {
+        Ok(selector) => selector.count(tree),
+        Err(_) => 0,
+    }
+}
+
+/// Check whether a node matches at least one of the selectors
+///
+/// Each selector is checked with [`Selector::matches`], so combinators are evaluated against
+/// the node's whole ancestor chain. Stops at the first match; malformed selectors never match.
+pub fn matches_any(node: &Handle, selectors: &[&str]) -> bool {
+    selectors.iter().any(|selector| {
+        Selector::parse(selector).is_ok_and(|selector| selector.matches(node))
+    })
+}
+
+/// Check whether a node matches every one of the selectors
+///
+/// Each selector is checked with [`Selector::matches`], so combinators are evaluated against
+/// the node's whole ancestor chain. Stops at the first selector that doesn't match; a
+/// malformed selector never matches. Returns `true` for an empty list.
+pub fn matches_all(node: &Handle, selectors: &[&str]) -> bool {
+    selectors.iter().all(|selector| {
+        Selector::parse(selector).is_ok_and(|selector| selector.matches(node))
+    })
+}
+
+/// Select the outermost matching nodes in the tree
+///
+/// Unlike [`select`], which returns every match, matches nested inside another match are
+/// dropped, so the returned nodes never overlap.
+///
+/// # Examples
+///
+/// ```
+/// use nexcrawl_html::select_outermost;
+/// use nexcrawl_html::node::{Node, NodeData};
+///
+/// let root = Node::new(NodeData::Document);
+/// let results = select_outermost(&root, ".item");
+/// ```
+pub fn select_outermost(tree: &Handle, selector: &str) -> Vec<Handle> {
+    outermost(select(tree, selector))
+}
+
+/// Detach the matching nodes from the tree, returning how many were removed
+///
+/// Matches nested inside another match go away with it and are not counted. `tree` itself is
+/// never removed, even if it matches. A malformed selector removes nothing.
+///
+/// # Examples
+///
+/// ```
+/// use nexcrawl_html::{parse_html, remove_matching, select_count};
+///
+/// let dom = parse_html("<div><p class='ad'>Buy</p><p>Text</p></div>");
+/// assert_eq!(remove_matching(&dom.tree, ".ad"), 1);
+/// assert_eq!(select_count(&dom.tree, "p"), 1);
+/// ```
+pub fn remove_matching(tree: &Handle, selector: &str) -> usize {
+    // All matches are collected before any is detached, since a detached node has no
+    // ancestors left to tell whether it is nested in another match
+    let matches: Vec<Handle> = select(tree, selector)
+        .into_iter()
+        .filter(|node| !node.same_node(tree))
+        .collect();
+    let outermost = outermost(matches);
+
+    for node in &outermost {
+        remove_from_parent(node);
     }
+    outermost.len()
+}
 
-    let mut results = Vec::new();
-    select_all_recursive(tree, &segments, 0, &mut results);
-    results
+/// Parse a comma-separated selector group into the segments of each selector (private)
+fn parse_selector_private(selector: &str) -> Result<Vec<Vec<SelectorSegment>>, SelectorError> {
+    if selector.trim().is_empty() {
+        return Err(SelectorError::Empty);
+    }
+
+    split_groups(selector)
+        .into_iter()
+        .map(|group| {
+            if group.trim().is_empty() {
+                // A leading, trailing or doubled comma
+                return Err(SelectorError::UnexpectedToken(",".to_string()));
+            }
+            parse_selector_impl(group)
+        })
+        .collect()
 }
 
-/// Parse a selector string into structured components (private)
-fn parse_selector_private(selector: &str) -> Vec<SelectorSegment> {
-    parse_selector_impl(selector)
+/// Split a selector group on the commas that are not inside `[...]` or `(...)`
+fn split_groups(selector: &str) -> Vec<&str> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    let mut in_brackets = false;
+    let mut paren_depth = 0usize;
+    let mut quote = None;
+
+    for (i, ch) in selector.char_indices() {
+        match (ch, quote) {
+            ('"' | '\'', None) if in_brackets => quote = Some(ch),
+            (c, Some(q)) if c == q => quote = None,
+            ('[', None) => in_brackets = true,
+            (']', None) => in_brackets = false,
+            ('(', None) if !in_brackets => paren_depth += 1,
+            (')', None) if !in_brackets => paren_depth = paren_depth.saturating_sub(1),
+            (',', None) if !in_brackets && paren_depth == 0 => {
+                groups.push(&selector[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    groups.push(&selector[start..]);
+    groups
 }
 
 /// Parse a selector string into structured components (implementation)
-fn parse_selector_impl(selector: &str) -> Vec<SelectorSegment> {
-    let segments: Vec<&str> = selector.trim().split_whitespace().collect();
-
-    segments.into_iter().map(|segment| {
-        let mut element: Option<String> = None;
-        let mut classes: Vec<String> = Vec::new();
-        let mut id: Option<String> = None;
-
-        let mut current_token = String::new();
-        let mut current_type = 'e'; // 'e' for element, 'c' for class, 'i' for id
-
-        for ch in segment.chars() {
-            match ch {
-                '.' => {
-                    if current_type == 'e' && !current_token.is_empty() {
-                        element = Some(current_token.clone());
-                    } else if current_type == 'c' && !current_token.is_empty() {
-                        classes.push(current_token.clone());
-                    } else if current_type == 'i' && !current_token.is_empty() {
-                        id = Some(current_token.clone());
+fn parse_selector_impl(selector: &str) -> Result<Vec<SelectorSegment>, SelectorError> {
+    let tokens = split_segments(selector)?;
+    if tokens.is_empty() {
+        return Err(SelectorError::Empty);
+    }
+
+    let mut segments = Vec::new();
+    let mut combinator = None;
+    for token in tokens.iter() {
+        if token == ">" {
+            // A combinator needs a segment on both sides
+            if segments.is_empty() || combinator.is_some() {
+                return Err(SelectorError::UnexpectedToken(token.clone()));
+            }
+            combinator = Some(Combinator::Child);
+            continue;
+        }
+
+        let mut segment = parse_segment(token)?;
+        segment.combinator = combinator.take().unwrap_or_default();
+        segments.push(segment);
+    }
+
+    if combinator.is_some() {
+        return Err(SelectorError::UnexpectedToken(">".to_string()));
+    }
+    Ok(segments)
+}
+
+/// Parse a single compound segment like `div.class#id[attr]:first-of-type`
+fn parse_segment(segment: &str) -> Result<SelectorSegment, SelectorError> {
+    let mut parsed = SelectorSegment::default();
+
+    let mut current_token = String::new();
+    // 'e' for element, 'c' for class, 'i' for id, 'a' for attribute, 'p' for pseudo-class
+    let mut current_type = 'e';
+
+    let mut chars = segment.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '.' | '#' | ':' => {
+                push_token(&mut parsed, current_type, std::mem::take(&mut current_token))?;
+                current_type = match ch {
+                    '.' => 'c',
+                    '#' => 'i',
+                    _ => 'p',
+                };
+            }
+            '[' => {
+                push_token(&mut parsed, current_type, std::mem::take(&mut current_token))?;
+                current_type = 'a';
+
+                // `split_segments` guarantees the bracket is closed
+                let mut group = String::new();
+                let mut quote = None;
+                for ch in chars.by_ref() {
+                    match (ch, quote) {
+                        (']', None) => break,
+                        ('"' | '\'', None) => quote = Some(ch),
+                        (c, Some(q)) if c == q => quote = None,
+                        _ => {}
                     }
-                    current_token.clear();
-                    current_type = 'c';
+                    group.push(ch);
                 }
-                '#' => {
-                    if current_type == 'e' && !current_token.is_empty() {
-                        element = Some(current_token.clone());
-                    } else if current_type == 'c' && !current_token.is_empty() {
-                        classes.push(current_token.clone());
+                parsed.attributes.push(parse_attribute_selector(&group)?);
+            }
+            '(' if current_type == 'p' => {
+                // Keep the argument with the pseudo-class name, e.g. `nth-child(2n+1)`;
+                // `split_segments` guarantees the parenthesis is closed
+                current_token.push(ch);
+                let mut depth = 1;
+                for ch in chars.by_ref() {
+                    current_token.push(ch);
+                    match ch {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
                     }
-                    current_token.clear();
-                    current_type = 'i';
                 }
-                _ => {
-                    current_token.push(ch);
+            }
+            ']' | '(' | ')' => return Err(SelectorError::UnexpectedToken(ch.to_string())),
+            _ => {
+                current_token.push(ch);
+            }
+        }
+    }
+
+    // Handle the last token
+    push_token(&mut parsed, current_type, current_token)?;
+
+    Ok(parsed)
+}
+
+/// Store a finished token of the given type in the segment
+fn push_token(
+    segment: &mut SelectorSegment,
+    token_type: char,
+    token: String,
+) -> Result<(), SelectorError> {
+    if token.is_empty() {
+        return match token_type {
+            'e' | 'a' => Ok(()),
+            // A dangling `.`, `#` or `:`
+            _ => Err(SelectorError::UnexpectedToken(token_type_prefix(token_type).to_string())),
+        };
+    }
+
+    match token_type {
+        'e' => match token.split_once('|') {
+            Some((prefix, local)) => {
+                if local.is_empty() || local.contains('|') {
+                    return Err(SelectorError::UnexpectedToken(token));
                 }
+                segment.namespace = Some(parse_namespace_prefix(prefix)?);
+                segment.element = Some(local.to_string());
             }
+            None => segment.element = Some(token),
+        },
+        'c' => segment.classes.push(token),
+        'i' => segment.id = Some(token),
+        'p' => segment.pseudo_classes.push(parse_pseudo_class(&token)?),
+        _ => return Err(SelectorError::UnexpectedToken(token)),
+    }
+    Ok(())
+}
+
+fn token_type_prefix(token_type: char) -> &'static str {
+    match token_type {
+        'c' => ".",
+        'i' => "#",
+        _ => ":",
+    }
+}
+
+/// Map a namespace prefix such as `svg` to its namespace
+fn parse_namespace_prefix(prefix: &str) -> Result<Namespace, SelectorError> {
+    match prefix.to_ascii_lowercase().as_str() {
+        "html" => Ok(ns!(html)),
+        "svg" => Ok(ns!(svg)),
+        "math" | "mathml" => Ok(ns!(mathml)),
+        _ => Err(SelectorError::UnknownNamespace(prefix.to_string())),
+    }
+}
+
+/// Parse a pseudo-class with its argument, if any, e.g. `first-of-type` or `nth-child(2)`
+fn parse_pseudo_class(token: &str) -> Result<PseudoClass, SelectorError> {
+    let (name, argument) = match token.split_once('(') {
+        // `parse_segment` only keeps a parenthesis together with its closing one
+        Some((name, rest)) => (name, rest.strip_suffix(')')),
+        None => (token, None),
+    };
+
+    match (name.to_ascii_lowercase().as_str(), argument) {
+        ("first-of-type", None) => Ok(PseudoClass::FirstOfType),
+        ("last-of-type", None) => Ok(PseudoClass::LastOfType),
+        ("scope", None) => Ok(PseudoClass::Scope),
+        ("root", None) => Ok(PseudoClass::Root),
+        ("first-child", None) => Ok(PseudoClass::FirstChild),
+        ("last-child", None) => Ok(PseudoClass::LastChild),
+        ("nth-child", Some(argument)) => {
+            let (a, b) = parse_nth(argument)
+                .ok_or_else(|| SelectorError::UnexpectedToken(argument.to_string()))?;
+            Ok(PseudoClass::NthChild { a, b })
         }
+        ("not", Some(argument)) => Ok(PseudoClass::Not(Box::new(parse_negated(argument)?))),
+        ("has", Some(argument)) => Ok(PseudoClass::Has(parse_relative(argument)?)),
+        ("nth-child" | "not" | "has", None) => {
+            Err(SelectorError::UnexpectedToken(token.to_string()))
+        }
+        _ => Err(SelectorError::UnknownPseudo(token.to_string())),
+    }
+}
+
+/// Parse the argument of `:not(...)`, which must be a single compound segment
+fn parse_negated(argument: &str) -> Result<SelectorSegment, SelectorError> {
+    let tokens = split_segments(argument)?;
+    match tokens.as_slice() {
+        [token] if token != ">" => parse_segment(token),
+        // Combinators and selector lists are not supported inside `:not`
+        _ => Err(SelectorError::UnexpectedToken(argument.to_string())),
+    }
+}
 
-        // Handle the last token
-        if !current_token.is_empty() {
-            match current_type {
-                'e' => element = Some(current_token),
-                'c' => classes.push(current_token),
-                'i' => id = Some(current_token),
-                _ => {}
+/// Parse the argument of `:has(...)`, a selector relative to the element, optionally
+/// starting with `>`
+///
+/// The returned segments start with a `:scope` segment for the element.
+fn parse_relative(argument: &str) -> Result<Vec<SelectorSegment>, SelectorError> {
+    let (combinator, selector) = match argument.trim().strip_prefix('>') {
+        Some(selector) => (Combinator::Child, selector),
+        None => (Combinator::Descendant, argument),
+    };
+    // Selector lists are not supported inside `:has`
+    if selector.trim().is_empty() || split_groups(selector).len() > 1 {
+        return Err(SelectorError::UnexpectedToken(argument.to_string()));
+    }
+
+    let mut segments = parse_selector_impl(selector)?;
+    segments[0].combinator = combinator;
+    let scope = SelectorSegment {
+        pseudo_classes: vec![PseudoClass::Scope],
+        ..Default::default()
+    };
+    segments.insert(0, scope);
+    Ok(segments)
+}
+
+/// Parse an `an+b` expression such as `3`, `2n+1`, `-n+3`, `odd` or `even` into `(a, b)`
+fn parse_nth(argument: &str) -> Option<(i32, i32)> {
+    let argument: String = argument
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    match argument.as_str() {
+        "odd" => return Some((2, 1)),
+        "even" => return Some((2, 0)),
+        _ => {}
+    }
+
+    let Some((a, b)) = argument.split_once('n') else {
+        return Some((0, argument.parse().ok()?));
+    };
+    let a = match a {
+        "" | "+" => 1,
+        "-" => -1,
+        _ => a.parse().ok()?,
+    };
+    // The offset needs an explicit sign, e.g. `2n+1` rather than `2n1`
+    let b = match b {
+        "" => 0,
+        _ if b.starts_with(['+', '-']) => b.parse().ok()?,
+        _ => return None,
+    };
+    Some((a, b))
+}
+
+/// Split a selector into whitespace-separated segments, ignoring whitespace inside `[...]`
+/// and `(...)`
+///
+/// A `>` combinator is returned as a segment of its own, with or without surrounding spaces.
+fn split_segments(selector: &str) -> Result<Vec<String>, SelectorError> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut in_brackets = false;
+    let mut paren_depth = 0usize;
+    let mut quote = None;
+
+    for ch in selector.chars() {
+        match (ch, quote) {
+            ('"' | '\'', None) if in_brackets => quote = Some(ch),
+            (c, Some(q)) if c == q => quote = None,
+            ('[', None) if in_brackets => {
+                return Err(SelectorError::UnexpectedToken("[".to_string()));
             }
+            ('[', None) => in_brackets = true,
+            (']', None) => in_brackets = false,
+            ('(', None) if !in_brackets => paren_depth += 1,
+            (')', None) if !in_brackets => {
+                if paren_depth == 0 {
+                    return Err(SelectorError::UnexpectedToken(")".to_string()));
+                }
+                paren_depth -= 1;
+            }
+            _ if paren_depth > 0 => {}
+            (c, None) if c.is_whitespace() && !in_brackets => {
+                if !current.is_empty() {
+                    segments.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+            ('>', None) if !in_brackets => {
+                if !current.is_empty() {
+                    segments.push(std::mem::take(&mut current));
+                }
+                segments.push(">".to_string());
+                continue;
+            }
+            _ => {}
         }
+        current.push(ch);
+    }
 
-        SelectorSegment { element, classes, id }
-    }).collect()
+    if in_brackets {
+        return Err(SelectorError::UnclosedBracket);
+    }
+    if paren_depth > 0 {
+        return Err(SelectorError::UnclosedParenthesis);
+    }
+    if !current.is_empty() {
+        segments.push(current);
+    }
+    Ok(segments)
+}
+
+/// Parse the inside of an attribute selector, e.g. `rel~=nofollow` or `href`
+fn parse_attribute_selector(
+    group: &str,
+) -> Result<(String, Option<String>, AttrMatch), SelectorError> {
+    // The first `=` ends the operator; later ones belong to the value
+    let (name, value, attr_match) = match group.split_once('=') {
+        None => (group, None, AttrMatch::Exact),
+        Some((name, value)) => {
+            let (name, attr_match) = match name.chars().last() {
+                Some('~') => (&name[..name.len() - 1], AttrMatch::ContainsWord),
+                Some('^') => (&name[..name.len() - 1], AttrMatch::Prefix),
+                Some('$') => (&name[..name.len() - 1], AttrMatch::Suffix),
+                Some('*') => (&name[..name.len() - 1], AttrMatch::Substring),
+                _ => (name, AttrMatch::Exact),
+            };
+            (name, Some(value), attr_match)
+        }
+    };
+
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(SelectorError::MissingAttributeName);
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':'))
+    {
+        return Err(SelectorError::UnexpectedToken(name.to_string()));
+    }
+
+    let value = match value.map(str::trim) {
+        None => None,
+        Some(value) => {
+            let unquoted = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')));
+            match unquoted {
+                Some(unquoted) => Some(unquoted.to_string()),
+                // Unquoted values must be a single token
+                None if value.is_empty() || value.contains(char::is_whitespace) => {
+                    return Err(SelectorError::UnexpectedToken(value.to_string()));
+                }
+                None => Some(value.to_string()),
+            }
+        }
+    };
+
+    Ok((name.to_string(), value, attr_match))
+}
+
+/// The state needed to match a selector against a node
+struct MatchContext<'a> {
+    options: &'a SelectorOptions,
+    /// Combinators never look above this node
+    root: Option<&'a Handle>,
+    /// The element matched by `:scope`
+    scope: Option<&'a Handle>,
+    /// Node visits left of `max_nodes`, shared with the nested queries of `:has`
+    budget: &'a Cell<usize>,
+}
+
+/// Take one node visit from the budget, or return `false` once it is exhausted
+fn take_visit(budget: &Cell<usize>) -> bool {
+    match budget.get() {
+        0 => false,
+        left => {
+            budget.set(left - 1);
+            true
+        }
+    }
+}
+
+impl MatchContext<'_> {
+    /// Get the parent of the node, unless the node is the root of the query
+    ///
+    /// Transparent `<slot>` parents are skipped.
+    fn parent(&self, node: &Handle) -> Option<Handle> {
+        let mut node = node.clone();
+        loop {
+            if self.root.is_some_and(|root| root.same_node(&node)) {
+                return None;
+            }
+            let parent = node.get_parent()?;
+            let is_slot = matches!(
+                &parent.data,
+                NodeData::Element { name, .. } if name.local.as_ref() == "slot"
+            );
+            if !(self.options.transparent_slots && is_slot) {
+                return Some(parent);
+            }
+            node = parent;
+        }
+    }
+}
+
+/// Visit the node and its descendants in document order, reporting the matching ones
+///
+/// A node matching several selectors of the group is reported once.
+fn select_all_recursive<F: FnMut(&Handle)>(
+    node: &Handle,
+    groups: &[Vec<SelectorSegment>],
+    context: &MatchContext,
+    on_match: &mut F,
+) {
+    if !take_visit(context.budget) {
+        return;
+    }
+
+    if matches_any_group(node, groups, context) {
+        on_match(node);
+    }
+
+    for child in node.children.borrow().iter() {
+        select_all_recursive(child, groups, context, on_match);
+    }
+}
+
+/// A selector matched without going through its segments, see [`Selector::fast_path`]
+enum FastPath<'a> {
+    /// `#id`
+    Id(&'a str),
+    /// A tag name, e.g. `p`
+    Tag(&'a str),
 }
 
-/// Unified recursive function for collecting all matches (both simple and descendant selectors)
-fn select_all_recursive(node: &Handle, segments: &[SelectorSegment], segment_index: usize, results: &mut Vec<Handle>) {
-    if segment_index >= segments.len() {
+impl FastPath<'_> {
+    /// Check if a node matches, the same way as [`matches_segment`]
+    fn matches(&self, node: &Handle, options: &SelectorOptions) -> bool {
+        let NodeData::Element { name, attrs, .. } = &node.data else {
+            return false;
+        };
+        match self {
+            FastPath::Tag(tag) => name.local.as_ref() == *tag,
+            FastPath::Id(id) => attrs.borrow().iter().any(|attr| {
+                attr.name.local.as_ref() == "id"
+                    && if options.ignore_id_class_case() {
+                        attr.value.eq_ignore_ascii_case(id)
+                    } else {
+                        &*attr.value == *id
+                    }
+            }),
+        }
+    }
+}
+
+/// Like [`select_all_recursive`], for a selector with a fast path
+fn select_all_fast<F: FnMut(&Handle)>(
+    node: &Handle,
+    fast_path: &FastPath,
+    options: &SelectorOptions,
+    budget: &Cell<usize>,
+    on_match: &mut F,
+) {
+    if !take_visit(budget) {
         return;
     }
 
-    let current_segment = &segments[segment_index];
-
-    // Check if current node matches the current segment
-    if matches_segment(node, current_segment) {
-        // If this is the last segment, we found a match
-        if segment_index == segments.len() - 1 {
-            results.push(node.clone());
-        } else {
-            // Otherwise, search descendants for the next segment
-            for child in node.children.borrow().iter() {
-                select_all_recursive(child, segments, segment_index + 1, results);
+    if fast_path.matches(node, options) {
+        on_match(node);
+    }
+
+    for child in node.children.borrow().iter() {
+        select_all_fast(child, fast_path, options, budget, on_match);
+    }
+}
+
+/// Like [`select_first_recursive`], for a selector with a fast path
+fn select_first_fast(
+    node: &Handle,
+    fast_path: &FastPath,
+    options: &SelectorOptions,
+    budget: &Cell<usize>,
+) -> Option<Handle> {
+    if !take_visit(budget) {
+        return None;
+    }
+
+    if fast_path.matches(node, options) {
+        return Some(node.clone());
+    }
+
+    node.children
+        .borrow()
+        .iter()
+        .find_map(|child| select_first_fast(child, fast_path, options, budget))
+}
+
+/// Find the first node in document order that matches, stopping the traversal there
+fn select_first_recursive(
+    node: &Handle,
+    groups: &[Vec<SelectorSegment>],
+    context: &MatchContext,
+) -> Option<Handle> {
+    if !take_visit(context.budget) {
+        return None;
+    }
+
+    if matches_any_group(node, groups, context) {
+        return Some(node.clone());
+    }
+
+    node.children
+        .borrow()
+        .iter()
+        .find_map(|child| select_first_recursive(child, groups, context))
+}
+
+/// Check if a node matches at least one selector of the group
+fn matches_any_group(
+    node: &Handle,
+    groups: &[Vec<SelectorSegment>],
+    context: &MatchContext,
+) -> bool {
+    groups
+        .iter()
+        .any(|segments| matches_selector(node, segments, context))
+}
+
+/// Check if a node matches the last segment, and its ancestors the segments before it
+fn matches_selector(node: &Handle, segments: &[SelectorSegment], context: &MatchContext) -> bool {
+    let Some((last, rest)) = segments.split_last() else {
+        return true;
+    };
+    if !matches_segment(node, last, context) {
+        return false;
+    }
+    if rest.is_empty() {
+        return true;
+    }
+
+    match last.combinator {
+        Combinator::Child => context
+            .parent(node)
+            .is_some_and(|parent| matches_selector(&parent, rest, context)),
+        Combinator::Descendant => {
+            let mut current = context.parent(node);
+            while let Some(ancestor) = current {
+                if matches_selector(&ancestor, rest, context) {
+                    return true;
+                }
+                current = context.parent(&ancestor);
             }
+            false
         }
     }
-
-    // Continue searching in children for current segment
-    for child in node.children.borrow().iter() {
-        select_all_recursive(child, segments, segment_index, results);
-    }
 }
 
 /// Check if a node matches a selector segment
-fn matches_segment(node: &Handle, segment: &SelectorSegment) -> bool {
+fn matches_segment(node: &Handle, segment: &SelectorSegment, context: &MatchContext) -> bool {
+    let options = context.options;
     match &node.data {
         NodeData::Element { name, attrs, .. } => {
             // Check element name match
             if let Some(ref element_name) = segment.element {
-                if name.local.as_ref() != element_name {
+                if element_name != UNIVERSAL && name.local.as_ref() != element_name {
+                    return false;
+                }
+                // A namespace prefix takes precedence over the default namespace
+                let namespace = segment.namespace.as_ref().or(options.default_namespace.as_ref());
+                if let Some(ns) = namespace
+                    && name.ns != *ns
+                {
                     return false;
                 }
             }
 
             let borrowed_attrs = attrs.borrow();
+            let id_class_eq = |actual: &str, expected: &str| {
+                if options.ignore_id_class_case() {
+                    actual.eq_ignore_ascii_case(expected)
+                } else {
+                    actual == expected
+                }
+            };
 
             // Check ID match
             if let Some(ref required_id) = segment.id {
                 let has_matching_id = borrowed_attrs.iter().any(|attr| {
-                    attr.name.local.as_ref() == "id" && attr.value.as_ref() == required_id
+                    attr.name.local.as_ref() == "id" && id_class_eq(&attr.value, required_id)
                 });
                 if !has_matching_id {
                     return false;
                 }
             }
 
+            let value_eq = |actual: &str, expected: &str| {
+                if options.case_insensitive_values {
+                    actual.eq_ignore_ascii_case(expected)
+                } else {
+                    actual == expected
+                }
+            };
+
+            // Check attribute matches
+            for (attr_name, expected, attr_match) in &segment.attributes {
+                let attr = borrowed_attrs
+                    .iter()
+                    .find(|attr| attr.name.local.as_ref().eq_ignore_ascii_case(attr_name));
+                let Some(attr) = attr else {
+                    return false;
+                };
+
+                let matches = match (expected, attr_match) {
+                    (None, _) => true,
+                    (Some(expected), AttrMatch::Exact) => value_eq(&attr.value, expected),
+                    (Some(expected), AttrMatch::ContainsWord) => attr
+                        .value
+                        .split_ascii_whitespace()
+                        .any(|word| value_eq(word, expected)),
+                    // Like CSS, an empty value never matches these operators
+                    (Some(expected), AttrMatch::Prefix) => {
+                        !expected.is_empty()
+                            && attr.value.len() >= expected.len()
+                            && attr.value.is_char_boundary(expected.len())
+                            && value_eq(&attr.value[..expected.len()], expected)
+                    }
+                    (Some(expected), AttrMatch::Suffix) => {
+                        let start = attr.value.len().wrapping_sub(expected.len());
+                        !expected.is_empty()
+                            && attr.value.len() >= expected.len()
+                            && attr.value.is_char_boundary(start)
+                            && value_eq(&attr.value[start..], expected)
+                    }
+                    (Some(expected), AttrMatch::Substring) => {
+                        !expected.is_empty()
+                            && if context.options.case_insensitive_values {
+                                attr.value
+                                    .to_ascii_lowercase()
+                                    .contains(&expected.to_ascii_lowercase())
+                            } else {
+                                attr.value.contains(expected.as_str())
+                            }
+                    }
+                };
+                if !matches {
+                    return false;
+                }
+            }
+
+            // Check pseudo-class matches
+            for pseudo_class in &segment.pseudo_classes {
+                if !matches_pseudo_class(node, pseudo_class, context) {
+                    return false;
+                }
+            }
+
             // Check class matches
             if !segment.classes.is_empty() {
                 let class_attr = borrowed_attrs.iter().find(|attr| {
@@ -167,7 +1324,10 @@ fn matches_segment(node: &Handle, segment: &SelectorSegment) -> bool {
 
                     // All required classes must be present
                     for required_class in &segment.classes {
-                        if !node_classes.contains(&required_class.as_str()) {
+                        if !node_classes
+                            .iter()
+                            .any(|class| id_class_eq(class, required_class))
+                        {
                             return false;
                         }
                     }
@@ -183,6 +1343,95 @@ fn matches_segment(node: &Handle, segment: &SelectorSegment) -> bool {
     }
 }
 
+/// Check if a node matches a pseudo-class
+fn matches_pseudo_class(node: &Handle, pseudo_class: &PseudoClass, context: &MatchContext) -> bool {
+    match pseudo_class {
+        PseudoClass::FirstOfType => same_type_siblings(node)
+            .first()
+            .is_none_or(|first| Rc::ptr_eq(first, node)),
+        PseudoClass::LastOfType => same_type_siblings(node)
+            .last()
+            .is_none_or(|last| Rc::ptr_eq(last, node)),
+        PseudoClass::Scope => context.scope.is_some_and(|scope| scope.same_node(node)),
+        // Like CSS, this is the top element rather than the `Document` node, so it is
+        // independent of the root the query starts from
+        PseudoClass::Root => node
+            .get_parent()
+            .is_none_or(|parent| matches!(parent.data, NodeData::Document)),
+        PseudoClass::FirstChild => element_position(node).0 == 1,
+        PseudoClass::LastChild => {
+            let (position, count) = element_position(node);
+            position == count
+        }
+        PseudoClass::NthChild { a, b } => {
+            let offset = element_position(node).0 as i64 - *b as i64;
+            match *a as i64 {
+                0 => offset == 0,
+                a => offset % a == 0 && offset / a >= 0,
+            }
+        }
+        PseudoClass::Not(inner) => !matches_segment(node, inner, context),
+        PseudoClass::Has(segments) => {
+            // Combinators inside never look above the element, which is also the `:scope`.
+            // The nodes visited count against the budget of the whole query.
+            let inner_context = MatchContext {
+                options: context.options,
+                root: Some(node),
+                scope: Some(node),
+                budget: context.budget,
+            };
+            node.children.borrow().iter().any(|child| {
+                let groups = std::slice::from_ref(segments);
+                select_first_recursive(child, groups, &inner_context).is_some()
+            })
+        }
+    }
+}
+
+/// Get the 1-based position of the node among its parent's element children, and the
+/// number of those children
+///
+/// Text and comment siblings are not counted. A node without a parent is at `(1, 1)`.
+fn element_position(node: &Handle) -> (usize, usize) {
+    let Some(parent) = node.get_parent() else {
+        return (1, 1);
+    };
+
+    let mut position = 0;
+    let mut count = 0;
+    for sibling in parent.children.borrow().iter() {
+        if matches!(sibling.data, NodeData::Element { .. }) {
+            count += 1;
+            if Rc::ptr_eq(sibling, node) {
+                position = count;
+            }
+        }
+    }
+    (position, count)
+}
+
+/// Get the sibling elements (including the node itself) with the same name as the node
+///
+/// Returns an empty vector if the node has no parent.
+fn same_type_siblings(node: &Handle) -> Vec<Handle> {
+    let NodeData::Element { name, .. } = &node.data else {
+        return Vec::new();
+    };
+    let Some(parent) = node.get_parent() else {
+        return Vec::new();
+    };
+
+    parent
+        .children
+        .borrow()
+        .iter()
+        .filter(|sibling| {
+            matches!(&sibling.data, NodeData::Element { name: sibling_name, .. } if sibling_name == name)
+        })
+        .cloned()
+        .collect()
+}
+
 /// Get the selector string for a node
 ///
 /// # Example
@@ -190,6 +1439,21 @@ fn matches_segment(node: &Handle, segment: &SelectorSegment) -> bool {
 /// Input: <div class="test">Hello</div>
 /// Output: div.test
 pub fn get_selector(node: &Handle) -> Option<String> {
+    let mut selector = own_selector(node)?;
+
+    // Get the parent selector; `get_parent` puts the weak pointer back after reading it
+    if let Some(parent) = node.get_parent() {
+        let parent_selector = get_selector(&parent);
+        if let Some(parent_selector) = parent_selector {
+            selector = format!("{} {}", parent_selector, selector);
+        }
+    }
+
+    Some(selector)
+}
+
+/// Get the selector of the element itself, without its ancestors, e.g. `div.test#myid`
+fn own_selector(node: &Handle) -> Option<String> {
     match &node.data {
         NodeData::Element { name, attrs, .. } => {
             let mut selector = name.local.to_string();
@@ -207,18 +1471,97 @@ pub fn get_selector(node: &Handle) -> Option<String> {
                     _ => {}
                 }
             }
+            Some(selector)
+        }
+        _ => None
+    }
+}
 
-            // Get the parent selector
-            if let Some(weak) = node.parent.take() && let Some(parent) = weak.upgrade() {
-                let parent_selector = get_selector(&parent);
-                if let Some(parent_selector) = parent_selector {
-                    selector = format!("{} {}", parent_selector, selector);
-                }
+/// Generates the same selectors as [`get_selector`], caching the selector of every node
+/// it passes
+///
+/// Generating the selectors of many nodes of a page with [`get_selector`] walks their shared
+/// ancestors again for every node. The generator walks up only to the closest ancestor it has
+/// already seen, so generating the selectors of a whole tree is close to linear.
+///
+/// The cache holds on to the nodes it has seen and assumes their classes, ids and parents
+/// don't change; call [`SelectorGenerator::clear`] after modifying the tree.
+///
+/// # Examples
+///
+/// ```
+/// use nexcrawl_html::{SelectorGenerator, parse_html, select};
+///
+/// let dom = parse_html("<div id='main'><p>One</p><p class='note'>Two</p></div>");
+/// let generator = SelectorGenerator::new();
+/// let selectors: Vec<_> = select(&dom.tree, "p")
+///     .iter()
+///     .filter_map(|p| generator.generate(p))
+///     .collect();
+/// assert_eq!(selectors, ["html body div#main p", "html body div#main p.note"]);
+/// ```
+#[derive(Default)]
+pub struct SelectorGenerator {
+    /// The selector of each seen node by address
+    cache: RefCell<HashMap<*const Node, CachedSelector>>,
+    /// Number of selectors built rather than read from the cache
+    computed: Cell<usize>,
+}
+
+struct CachedSelector {
+    /// Keeps the address of the node from being reused by another node
+    _node: Handle,
+    selector: Option<Rc<str>>,
+}
+
+impl SelectorGenerator {
+    /// Create a generator with an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the selector string for a node, see [`get_selector`]
+    pub fn generate(&self, node: &Handle) -> Option<String> {
+        // Walk up to the closest ancestor with a cached selector; like `get_selector`, the
+        // walk also stops at the first node that is not an element
+        let mut uncached = Vec::new();
+        let mut prefix = None;
+        let mut current = Some(node.clone());
+        while let Some(node) = current {
+            if let Some(cached) = self.cache.borrow().get(&Rc::as_ptr(&node)) {
+                prefix = cached.selector.clone();
+                break;
             }
+            current = match node.data {
+                NodeData::Element { .. } => node.get_parent(),
+                _ => None,
+            };
+            uncached.push(node);
+        }
 
-            Some(selector)
+        // Build the selectors back down, starting below the cached ancestor
+        for node in uncached.into_iter().rev() {
+            let selector: Option<Rc<str>> = own_selector(&node).map(|own| match &prefix {
+                Some(prefix) => format!("{} {}", prefix, own).into(),
+                None => own.into(),
+            });
+            self.computed.set(self.computed.get() + 1);
+            self.cache.borrow_mut().insert(
+                Rc::as_ptr(&node),
+                CachedSelector {
+                    _node: node.clone(),
+                    selector: selector.clone(),
+                },
+            );
+            prefix = selector;
         }
-        _ => None
+
+        prefix.map(|selector| selector.to_string())
+    }
+
+    /// Forget all cached selectors
+    pub fn clear(&self) {
+        self.cache.borrow_mut().clear();
     }
 }
 
@@ -226,34 +1569,151 @@ pub fn get_selector(node: &Handle) -> Option<String> {
 mod tests {
     use super::*;
     use crate::node::{Node, NodeData};
-    use html5ever::{QualName, Attribute, LocalName, Namespace};
-    use std::cell::RefCell;
 
     // Test helper function to expose parse_selector functionality
     fn parse_selector(selector: &str) -> Vec<SelectorSegment> {
-        parse_selector_impl(selector)
+        parse_selector_impl(selector).unwrap()
+    }
+
+    // Test helper selecting through segment matching, even when a fast path exists
+    fn select_general(tree: &Handle, selector: &str, options: &SelectorOptions) -> Vec<Handle> {
+        let selector = Selector::parse(selector).unwrap();
+        let budget = Cell::new(options.node_budget());
+        let context = MatchContext {
+            options,
+            root: Some(tree),
+            scope: None,
+            budget: &budget,
+        };
+        let mut results = Vec::new();
+        select_all_recursive(tree, &selector.groups, &context, &mut |node| {
+            results.push(node.clone())
+        });
+        results
+    }
+
+    // A synthetic page with `sections` sections of ten paragraphs each
+    fn synthetic_tree(sections: usize) -> Handle {
+        let body = Node::new_element("body", &[]);
+        for i in 0..sections {
+            let id = format!("section-{i}");
+            let section = Node::new_element("section", &[("id", &id), ("class", "s")]);
+            for j in 0..10 {
+                let tag = if j % 3 == 0 { "span" } else { "p" };
+                let p = Node::new_element(tag, &[("id", if j == 5 { "dup" } else { "x" })]);
+                p.children.borrow_mut().push(Node::new_text(format!("text {j}")));
+                section.children.borrow_mut().push(p);
+            }
+            body.children.borrow_mut().push(section);
+        }
+        let root = Node::new(NodeData::Document);
+        root.children.borrow_mut().push(body);
+        root
+    }
+
+    fn same_nodes(a: &[Handle], b: &[Handle]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| Rc::ptr_eq(a, b))
     }
 
     #[test]
-    fn test_get_selector_div_with_class_and_id() {
-        let name = QualName::new(None, Namespace::from(""), LocalName::from("div"));
-        let attrs = vec![
-            Attribute {
-                name: QualName::new(None, Namespace::from(""), LocalName::from("class")),
-                value: "test".into(),
-            },
-            Attribute {
-                name: QualName::new(None, Namespace::from(""), LocalName::from("id")),
-                value: "myid".into(),
-            },
-        ];
+    fn test_fast_path_matches_general_path() {
+        let tree = synthetic_tree(20);
+        let quirks = SelectorOptions {
+            quirks_mode: QuirksMode::Quirks,
+            ..Default::default()
+        };
+        let limited = SelectorOptions {
+            max_nodes: Some(37),
+            ..Default::default()
+        };
+        let svg = SelectorOptions {
+            default_namespace: Some(ns!(svg)),
+            ..Default::default()
+        };
+        for options in [&SelectorOptions::default(), &quirks, &limited, &svg] {
+            for selector in ["p", "span", "section", "#section-3", "#dup", "#DUP", "#none", "a"] {
+                let fast = select_with_options(&tree, selector, options);
+                let general = select_general(&tree, selector, options);
+                assert!(same_nodes(&fast, &general), "{selector} with {options:?}");
+                assert_eq!(
+                    Selector::parse(selector).unwrap().count_with_options(&tree, options),
+                    general.len()
+                );
+            }
+        }
 
-        let node = Node::new(NodeData::Element {
-            name,
-            attrs: RefCell::new(attrs),
-            template_contents: RefCell::new(None),
-            mathml_annotation_xml_integration_point: false,
-        });
+        assert!(Selector::parse("p").unwrap().fast_path(&svg).is_none());
+        assert!(Selector::parse("p.a").unwrap().fast_path(&quirks).is_none());
+        assert!(Selector::parse("*").unwrap().fast_path(&quirks).is_none());
+        assert!(Selector::parse("p, #dup").unwrap().fast_path(&quirks).is_none());
+
+        let first = select_first(&tree, "#dup").unwrap();
+        assert!(Rc::ptr_eq(&first, &select_general(&tree, "#dup", &quirks)[0]));
+        assert_eq!(select_count(&tree, "#DUP"), 0);
+        assert_eq!(select_with_options(&tree, "#DUP", &quirks).len(), 20);
+    }
+
+    #[test]
+    fn test_get_selector_keeps_parents() {
+        let root = Node::new_element("div", &[("id", "root")]);
+        let section = Node::new_element("section", &[]);
+        let leaf = Node::new_element("p", &[("class", "leaf")]);
+        section.children.borrow_mut().push(leaf.clone());
+        leaf.parent.set(Some(Rc::downgrade(&section)));
+        root.children.borrow_mut().push(section.clone());
+        section.parent.set(Some(Rc::downgrade(&root)));
+
+        assert_eq!(get_selector(&leaf), Some("div#root section p.leaf".to_string()));
+        // Calling it again gives the same result, since no parent pointer was consumed
+        assert_eq!(get_selector(&leaf), Some("div#root section p.leaf".to_string()));
+        assert!(leaf.get_parent().unwrap().same_node(&section));
+        assert!(section.get_parent().unwrap().same_node(&root));
+    }
+
+    #[test]
+    fn test_selector_generator() {
+        let dom = crate::parse::parse_html(
+            "<div id='main'><section class='a b'><p>1</p><p>2</p></section>\
+            <section><ul><li>3</li><li>4</li></ul></section></div>",
+        );
+        // Elements without child elements: head, both p and both li
+        let leaves: Vec<_> = select(&dom.tree, "*")
+            .into_iter()
+            .filter(|node| {
+                let children = node.children.borrow();
+                !children.iter().any(|child| matches!(child.data, NodeData::Element { .. }))
+            })
+            .collect();
+        assert_eq!(leaves.len(), 5);
+
+        let generator = SelectorGenerator::new();
+        for leaf in &leaves {
+            assert_eq!(generator.generate(leaf), get_selector(leaf));
+        }
+        // Only the 12 nodes from the document down to the leaves are built, once each;
+        // walking up from every leaf separately would visit 29 nodes
+        assert_eq!(generator.computed.get(), 12);
+
+        // Cached selectors are reused as is
+        let li = select(&dom.tree, "li").remove(1);
+        assert_eq!(
+            generator.generate(&li).as_deref(),
+            Some("html body div#main section ul li")
+        );
+        assert_eq!(generator.computed.get(), 12);
+
+        generator.clear();
+        assert!(generator.generate(&li).is_some());
+        assert_eq!(generator.computed.get(), 19);
+
+        // Like `get_selector`, nodes that are not elements have no selector
+        let text = li.children.borrow()[0].clone();
+        assert_eq!(generator.generate(&text), None);
+    }
+
+    #[test]
+    fn test_get_selector_div_with_class_and_id() {
+        let node = Node::new_element("div", &[("class", "test"), ("id", "myid")]);
 
         let selector = get_selector(&node);
         assert_eq!(selector, Some("div.test#myid".to_string()));
@@ -261,20 +1721,7 @@ mod tests {
 
     #[test]
     fn test_get_selector_with_multiple_classes() {
-        let name = QualName::new(None, Namespace::from(""), LocalName::from("div"));
-        let attrs = vec![
-            Attribute {
-                name: QualName::new(None, Namespace::from(""), LocalName::from("class")),
-                value: "test1 test2".into(),
-            },
-        ];
-
-        let node = Node::new(NodeData::Element {
-            name,
-            attrs: RefCell::new(attrs),
-            template_contents: RefCell::new(None),
-            mathml_annotation_xml_integration_point: false,
-        });
+        let node = Node::new_element("div", &[("class", "test1 test2")]);
 
         let selector = get_selector(&node);
         assert_eq!(selector, Some("div.test1.test2".to_string()));
@@ -331,29 +1778,18 @@ mod tests {
     }
 
     fn create_test_node(tag: &str, classes: &[&str], id: Option<&str>) -> Handle {
-        let name = QualName::new(None, Namespace::from(""), LocalName::from(tag));
+        let class = classes.join(" ");
         let mut attrs = Vec::new();
 
         if !classes.is_empty() {
-            attrs.push(Attribute {
-                name: QualName::new(None, Namespace::from(""), LocalName::from("class")),
-                value: classes.join(" ").into(),
-            });
+            attrs.push(("class", class.as_str()));
         }
 
         if let Some(id_val) = id {
-            attrs.push(Attribute {
-                name: QualName::new(None, Namespace::from(""), LocalName::from("id")),
-                value: id_val.into(),
-            });
+            attrs.push(("id", id_val));
         }
 
-        Node::new(NodeData::Element {
-            name,
-            attrs: RefCell::new(attrs),
-            template_contents: RefCell::new(None),
-            mathml_annotation_xml_integration_point: false,
-        })
+        Node::new_element(tag, &attrs)
     }
 
     #[test]
@@ -551,6 +1987,845 @@ mod tests {
         assert!(results.is_empty());
     }
 
+    #[test]
+    fn test_is_descendant_of() {
+        let tree = create_tree();
+        let p = select(&tree, "p").remove(0);
+        let div = select(&tree, "div.active").remove(0);
+        let span = select(&tree, "span").remove(0);
+
+        assert!(p.is_descendant_of(&div));
+        assert!(p.is_descendant_of(&tree));
+        assert!(!p.is_descendant_of(&span));
+        assert!(!p.is_descendant_of(&p));
+        assert!(!tree.is_descendant_of(&p));
+
+        assert!(tree.is_ancestor_of(&p));
+        assert!(div.is_ancestor_of(&p));
+        assert!(!span.is_ancestor_of(&p));
+        assert!(!p.is_ancestor_of(&div));
+    }
+
+    #[test]
+    fn test_is_descendant_of_dropped_parent() {
+        let p = {
+            let tree = create_tree();
+            select(&tree, "p").remove(0)
+        };
+        let other = create_tree();
+
+        // The weak parent pointers of `p` are dangling now
+        assert!(!p.is_descendant_of(&other));
+        assert!(!other.is_ancestor_of(&p));
+    }
+
+    #[test]
+    fn test_select_outermost() {
+        let tree = create_tree();
+
+        // Nest another item inside the active item
+        let div = select(&tree, "div.active").remove(0);
+        let nested = create_test_node("span", &["item"], None);
+        nested.parent.set(Some(std::rc::Rc::downgrade(&div)));
+        div.children.borrow_mut().push(nested);
+
+        assert_eq!(select(&tree, ".item").len(), 4);
+
+        let results = select_outermost(&tree, ".item");
+        assert_eq!(results.len(), 3);
+        assert!(std::rc::Rc::ptr_eq(&results[1], &div));
+    }
+
+    #[test]
+    fn test_remove_matching() {
+        use crate::node::serialize_to_string;
+        use crate::parse::parse_html;
+
+        let html = r#"<div id="main"><p>One</p><div class="ad">Ad <span class="ad">nested</span></div><p>Two <b class="ad">inline</b></p><aside class="ad"><div class="ad"><i class="ad">x</i></div></aside></div>"#;
+        let dom = parse_html(html);
+        let main = select_first(&dom.tree, "#main").unwrap();
+        let ad = select_first(&main, "div.ad").unwrap();
+        let nested = select_first(&ad, "span.ad").unwrap();
+
+        // Nested ads go away with their outermost ad
+        assert_eq!(remove_matching(&main, ".ad"), 3);
+        assert_eq!(serialize_to_string(&main).unwrap(), "<p>One</p><p>Two </p>");
+        assert!(select(&main, ".ad").is_empty());
+        // Detached subtrees are left intact
+        assert!(ad.get_parent().is_none());
+        assert!(nested.get_parent().unwrap().same_node(&ad));
+
+        // The tree itself is never removed, and a malformed selector removes nothing
+        assert_eq!(remove_matching(&main, "div"), 0);
+        assert!(main.get_parent().is_some());
+        assert_eq!(remove_matching(&main, "p["), 0);
+        assert_eq!(remove_matching(&main, "p"), 2);
+        assert!(main.children.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_select_quirks_mode() {
+        use crate::parse::parse_html;
+
+        let html = r#"<div id="Main" class="Item Active"></div>"#;
+
+        // No doctype: quirks mode
+        let dom = parse_html(html);
+        assert_eq!(dom.quirks_mode.get(), QuirksMode::Quirks);
+        let options = SelectorOptions::for_dom(&dom);
+        assert!(select(&dom.tree, "#main").is_empty());
+        assert!(select(&dom.tree, ".item").is_empty());
+        assert_eq!(select_with_options(&dom.tree, "#main", &options).len(), 1);
+        assert_eq!(select_with_options(&dom.tree, "div.item.active", &options).len(), 1);
+
+        // Standards mode stays case-sensitive
+        let dom = parse_html(&format!("<!DOCTYPE html>{}", html));
+        let options = SelectorOptions::for_dom(&dom);
+        assert!(select_with_options(&dom.tree, "#main", &options).is_empty());
+        assert_eq!(select_with_options(&dom.tree, "#Main", &options).len(), 1);
+    }
+
+    #[test]
+    fn test_select_case_insensitive_values() {
+        let node = Node::new_element(
+            "a",
+            &[("id", "Main"), ("class", "Item"), ("rel", "NoFollow"), ("type", "TEXT")],
+        );
+        let options = SelectorOptions {
+            case_insensitive_values: true,
+            ..Default::default()
+        };
+
+        for selector in ["#main", ".item", "[rel~=nofollow]", "[type=text]", "a#MAIN.ITEM"] {
+            assert!(select(&node, selector).is_empty(), "{}", selector);
+            assert_eq!(select_with_options(&node, selector, &options).len(), 1, "{}", selector);
+        }
+
+        let compiled = Selector::parse("[type=text]").unwrap();
+        assert!(compiled.select(&node).is_empty());
+        assert_eq!(compiled.select_with_options(&node, &options).len(), 1);
+    }
+
+    #[test]
+    fn test_select_max_nodes() {
+        let tree = create_tree();
+        assert_eq!(select(&tree, ".item").len(), 3);
+
+        // The root and its first child are visited before the budget runs out
+        let options = SelectorOptions {
+            max_nodes: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(select_with_options(&tree, ".item", &options).len(), 1);
+        assert_eq!(select_count(&tree, ".item"), 3);
+
+        let options = SelectorOptions {
+            max_nodes: Some(0),
+            ..Default::default()
+        };
+        assert!(select_with_options(&tree, "div", &options).is_empty());
+    }
+
+    #[test]
+    fn test_has_shares_max_nodes() {
+        let div = Node::new_element("div", &[]);
+        for tag in ["b", "b", "p"] {
+            let child = Node::new_element(tag, &[]);
+            child.parent.set(Some(Rc::downgrade(&div)));
+            div.children.borrow_mut().push(child);
+        }
+        let with_budget = |max_nodes| SelectorOptions {
+            max_nodes: Some(max_nodes),
+            ..Default::default()
+        };
+
+        // The search inside `:has` spends the visits of the whole query: with 4 visits, the
+        // `<div>` and its three children fit, with 3 the `<p>` is never reached
+        assert_eq!(select_with_options(&div, "div:has(p)", &with_budget(4)).len(), 1);
+        assert!(select_with_options(&div, "div:has(p)", &with_budget(3)).is_empty());
+        assert_eq!(
+            Selector::parse("div:has(p)").unwrap().count_with_options(&div, &with_budget(3)),
+            0
+        );
+    }
+
+    #[test]
+    fn test_specificity() {
+        let specificity = |selector: &str| Selector::parse(selector).unwrap().specificity();
+
+        assert_eq!(specificity("#id"), (1, 0, 0));
+        assert_eq!(specificity(".class"), (0, 1, 0));
+        assert_eq!(specificity("div"), (0, 0, 1));
+        assert!(specificity("#id") > specificity(".class"));
+        assert!(specificity(".class") > specificity("div"));
+        assert!(specificity(".a.b") > specificity("div .a"));
+
+        assert_eq!(
+            specificity("ul#nav > li.item[data-x]:first-of-type a"),
+            (1, 3, 3)
+        );
+    }
+
+    #[test]
+    fn test_root_pseudo_class() {
+        let dom = crate::parse::parse_html("<html><body><div><p>Text</p></div></body></html>");
+        let tag = |node: &Handle| match &node.data {
+            NodeData::Element { name, .. } => name.local.to_string(),
+            _ => String::new(),
+        };
+
+        let root = select(&dom.tree, ":root");
+        assert_eq!(root.len(), 1);
+        assert_eq!(tag(&root[0]), "html");
+
+        let body = select(&dom.tree, ":root > body");
+        assert_eq!(body.len(), 1);
+        assert_eq!(tag(&body[0]), "body");
+
+        // Nested elements are not the root, even when the query starts below the document
+        let div = select(&dom.tree, "div").remove(0);
+        assert!(select(&div, ":root").is_empty());
+        assert!(select(&dom.tree, "div:root").is_empty());
+        assert_eq!(select(&dom.tree, ":root div").len(), 1);
+
+        // The top element of a detached tree is its root
+        let detached = Node::new_element("section", &[]);
+        assert_eq!(select(&detached, ":root").len(), 1);
+    }
+
+    #[test]
+    fn test_universal_selector() {
+        let dom = crate::parse::parse_html(
+            "<div><p>One <span class=\"highlight\">hit</span></p></div><span class=\"highlight\">top</span>",
+        );
+
+        // Every element of the parsed page: html, head, body, div, p, span, span
+        assert_eq!(select(&dom.tree, "*").len(), 7);
+        assert_eq!(select(&dom.tree, "* .highlight").len(), 2);
+        assert_eq!(select(&dom.tree, "p > *").len(), 1);
+        assert_eq!(select(&dom.tree, "*.highlight").len(), 2);
+
+        // `*` adds nothing to the specificity
+        assert_eq!(Selector::parse("* > *").unwrap().specificity(), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_child_position_pseudo_classes() {
+        let dom = crate::parse::parse_html(
+            "<ul> text <!-- comment --><li>1</li>\n<li>2</li><!-- comment --><li>3</li> <li>4</li>\n</ul>",
+        );
+        let texts = |selector: &str| {
+            select(&dom.tree, selector)
+                .iter()
+                .map(crate::node::text_content)
+                .collect::<Vec<_>>()
+        };
+
+        // Text and comment siblings are not counted
+        assert_eq!(texts("li:nth-child(2)"), ["2"]);
+        assert_eq!(texts("li:first-child"), ["1"]);
+        assert_eq!(texts("li:last-child"), ["4"]);
+        assert_eq!(texts("li:nth-child(odd)"), ["1", "3"]);
+        assert_eq!(texts("li:nth-child(even)"), ["2", "4"]);
+        assert_eq!(texts("li:nth-child(2n + 1)"), ["1", "3"]);
+        assert_eq!(texts("li:nth-child(-n+2)"), ["1", "2"]);
+        assert_eq!(texts("li:nth-child(n+3)"), ["3", "4"]);
+        assert!(texts("li:nth-child(5)").is_empty());
+        assert_eq!(texts("ul > :nth-child(3)"), ["3"]);
+    }
+
+    #[test]
+    fn test_nth_child_errors() {
+        assert_eq!(
+            Selector::parse("li:nth-child(2"),
+            Err(SelectorError::UnclosedParenthesis)
+        );
+        assert_eq!(
+            Selector::parse("li:nth-child(x)"),
+            Err(SelectorError::UnexpectedToken("x".to_string()))
+        );
+        assert_eq!(
+            Selector::parse("li:nth-child"),
+            Err(SelectorError::UnexpectedToken("nth-child".to_string()))
+        );
+        assert_eq!(
+            Selector::parse("li)"),
+            Err(SelectorError::UnexpectedToken(")".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_not_pseudo_class() {
+        let dom = crate::parse::parse_html(
+            r#"<div id="a">Article</div>
+            <div class="ad">Ad</div>
+            <div class="ad sponsored">Sponsored ad</div>
+            <div class="sponsored">Sponsored</div>
+            <p>Paragraph</p>"#,
+        );
+        let texts = |selector: &str| {
+            select(&dom.tree, selector)
+                .iter()
+                .map(crate::node::text_content)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(texts("div:not(.ad)"), ["Article", "Sponsored"]);
+        assert_eq!(
+            texts("div:not(.ad.sponsored)"),
+            ["Article", "Ad", "Sponsored"]
+        );
+        assert_eq!(texts("div:not(.ad):not(#a)"), ["Sponsored"]);
+        assert_eq!(texts("body > :not(div)"), ["Paragraph"]);
+
+        // Like CSS, the negation counts as its argument
+        assert_eq!(
+            Selector::parse("div:not(#a.ad)").unwrap().specificity(),
+            (1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn test_not_pseudo_class_errors() {
+        assert_eq!(
+            Selector::parse("div:not(section .ad)"),
+            Err(SelectorError::UnexpectedToken("section .ad".to_string()))
+        );
+        assert_eq!(
+            Selector::parse("div:not()"),
+            Err(SelectorError::UnexpectedToken("".to_string()))
+        );
+        assert_eq!(
+            Selector::parse("div:not"),
+            Err(SelectorError::UnexpectedToken("not".to_string()))
+        );
+        assert_eq!(
+            Selector::parse("div:not(:hover)"),
+            Err(SelectorError::UnknownPseudo("hover".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_has_pseudo_class() {
+        let dom = crate::parse::parse_html(
+            r#"<div id="a"><span class="price">3 EUR</span></div>
+            <div id="b"><p>No price</p></div>
+            <div id="c"><section><p class="price">5 EUR</p></section></div>
+            <ul><li id="d"><a href="/">Link</a></li><li id="e"><b><a href="/">Nested</a></b></li></ul>"#,
+        );
+        let ids = |selector: &str| {
+            select(&dom.tree, selector)
+                .iter()
+                .filter_map(|node| node.get_attribute("id"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids("div:has(.price)"), ["a", "c"]);
+        assert_eq!(ids("div:has(> .price)"), ["a"]);
+        assert_eq!(ids("div:has(section .price)"), ["c"]);
+        assert_eq!(ids("li:has(> a)"), ["d"]);
+        assert_eq!(ids("li:has(a)"), ["d", "e"]);
+        assert_eq!(ids("div:not(:has(.price))"), ["b"]);
+
+        // The element itself is not part of the relative selector
+        assert!(ids("div:has(div .price)").is_empty());
+        assert!(ids("section:has(section)").is_empty());
+
+        assert_eq!(
+            Selector::parse("div:has(> a.b)").unwrap().specificity(),
+            (0, 1, 2)
+        );
+    }
+
+    #[test]
+    fn test_has_pseudo_class_errors() {
+        for selector in ["div:has()", "div:has(>)", "div:has(a, b)", "div:has"] {
+            assert!(
+                matches!(Selector::parse(selector), Err(SelectorError::UnexpectedToken(_))),
+                "{}",
+                selector
+            );
+        }
+        assert_eq!(
+            Selector::parse("div:has(a >)"),
+            Err(SelectorError::UnexpectedToken(">".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_transparent_slots() {
+        let dom = crate::parse::parse_html(
+            "<section><slot name=\"body\"><div><slot><p>Slotted</p></slot></div></slot><p>Direct</p></section>",
+        );
+        let options = SelectorOptions {
+            transparent_slots: true,
+            ..Default::default()
+        };
+
+        assert_eq!(select(&dom.tree, "section > p").len(), 1);
+        assert_eq!(select_with_options(&dom.tree, "section > p", &options).len(), 1);
+        assert_eq!(select_with_options(&dom.tree, "section > div > p", &options).len(), 1);
+        assert_eq!(select_with_options(&dom.tree, "div > p", &options).len(), 1);
+        assert_eq!(select(&dom.tree, "div > p").len(), 0);
+        assert_eq!(select(&dom.tree, "section p").len(), 2);
+
+        // Slots can still be selected themselves
+        assert_eq!(select_with_options(&dom.tree, "section > slot", &options).len(), 1);
+        assert_eq!(select_with_options(&dom.tree, "div > slot", &options).len(), 1);
+
+        // The query root bounds the walk even through slots
+        let outer = select(&dom.tree, "slot[name=body]").remove(0);
+        assert_eq!(select_with_options(&outer, "section p", &options).len(), 0);
+        assert_eq!(select_with_options(&outer, "div > p", &options).len(), 1);
+    }
+
+    #[test]
+    fn test_matches_any_all() {
+        let dom = crate::parse::parse_html(
+            r#"<aside class="ad"><a class="track" href="https://ads.example">Buy</a></aside>"#,
+        );
+        let link = select(&dom.tree, "a").remove(0);
+
+        assert!(matches_any(&link, &["img", ".ad a"]));
+        assert!(!matches_any(&link, &["img", "div a", "["]));
+        assert!(!matches_any(&link, &[]));
+
+        assert!(matches_all(&link, &["a.track", "aside > a", "[href]"]));
+        assert!(!matches_all(&link, &["a.track", "section a"]));
+        assert!(!matches_all(&link, &["a", "["]));
+        assert!(matches_all(&link, &[]));
+    }
+
+    #[test]
+    fn test_select_parents() {
+        let dom = crate::parse::parse_html(
+            "<ul><li><a>One</a> <a>Two</a></li><li><a>Three</a></li><li>None</li></ul>",
+        );
+        let parents = select_parents(&dom.tree, "a");
+        assert_eq!(parents.len(), 2);
+        assert!(parents.iter().all(|parent| {
+            matches!(&parent.data, NodeData::Element { name, .. } if name.local.as_ref() == "li")
+        }));
+        assert!(!parents[0].same_node(&parents[1]));
+
+        // The root has no parent
+        let root = Node::new_element("a", &[]);
+        assert!(select_parents(&root, "a").is_empty());
+    }
+
+    #[test]
+    fn test_select_in() {
+        let tree = create_tree();
+        let div = tree.children.borrow()[1].clone();
+        let span = Node::new_element("span", &[("class", "item")]);
+
+        let results = select_in(&[span.clone(), tree.clone()], ".item");
+        assert_eq!(results.len(), 4);
+        assert!(results[0].same_node(&span));
+        assert!(results[2].same_node(&div));
+
+        // Overlapping roots do not produce duplicates
+        let results = select_in(&[tree.clone(), div.clone()], "div");
+        assert_eq!(results.len(), 2);
+        assert!(select_in(&[], "div").is_empty());
+    }
+
+    #[test]
+    fn test_tag_histogram() {
+        let histogram = crate::node::tag_histogram(&create_tree());
+        let expected = [("div", 2), ("span", 2), ("p", 1)]
+            .into_iter()
+            .map(|(tag, count)| (tag.to_string(), count))
+            .collect();
+        assert_eq!(histogram, expected);
+    }
+
+    #[test]
+    fn test_select_child_combinator() {
+        let tree = create_tree();
+        assert_eq!(select(&tree, "#root > .item").len(), 3);
+        assert_eq!(select(&tree, "div > p").len(), 1);
+        assert!(select(&tree, "#root > p").is_empty());
+        assert_eq!(select(&tree, "#root p").len(), 1);
+
+        // Matches are reported once even if several ancestor chains match
+        let outer = create_test_node("div", &[], None);
+        let inner = create_test_node("div", &[], None);
+        let p = create_test_node("p", &[], None);
+        inner.children.borrow_mut().push(p.clone());
+        p.parent.set(Some(Rc::downgrade(&inner)));
+        outer.children.borrow_mut().push(inner.clone());
+        inner.parent.set(Some(Rc::downgrade(&outer)));
+        assert_eq!(select(&outer, "div p").len(), 1);
+        assert_eq!(select(&outer, "div div p").len(), 1);
+
+        for selector in ["> p", "div >", "div > > p"] {
+            assert_eq!(
+                Selector::parse(selector),
+                Err(SelectorError::UnexpectedToken(">".to_string())),
+                "{}",
+                selector
+            );
+        }
+    }
+
+    #[test]
+    fn test_child_combinator_spacing_and_mixing() {
+        assert_eq!(parse_selector("div > .active"), parse_selector("div>.active"));
+        assert_eq!(parse_selector("div > .active"), parse_selector("div >.active"));
+        assert_eq!(parse_selector("div>.active")[1].combinator, Combinator::Child);
+        assert_eq!(parse_selector("a[x='>'] b")[0].attributes[0].1.as_deref(), Some(">"));
+
+        let dom = crate::parse::parse_html(
+            "<ul><li>One<ul><li>Nested</li></ul></li><li>Two</li></ul>\
+            <article><div><section><p class=\"text\">A</p></section></div></article>\
+            <article><section><div><p class=\"text\">B</p></div></section></article>\
+            <article><div><div><p class=\"text\">C</p></div></div></article>",
+        );
+        let texts = |selector: &str| {
+            select(&dom.tree, selector)
+                .iter()
+                .map(crate::node::text_content)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(select(&dom.tree, "body > ul > li").len(), 2);
+        assert_eq!(select(&dom.tree, "ul li").len(), 3);
+        // The outer `div` of C is the one that is a child of `article`
+        assert_eq!(texts("article > div .text"), vec!["A", "C"]);
+        assert_eq!(texts("article div > .text"), vec!["B", "C"]);
+        assert_eq!(texts("article > div > div > .text"), vec!["C"]);
+    }
+
+    #[test]
+    fn test_selector_groups() {
+        let dom = crate::parse::parse_html(
+            "<h2>B</h2><div class=\"container\"><h1>A</h1><p>Text</p></div><h3>C</h3>\
+            <div><span class=\"container\">D</span></div>",
+        );
+        let tags = |selector: &str| {
+            select(&dom.tree, selector)
+                .iter()
+                .map(|node| match &node.data {
+                    NodeData::Element { name, .. } => name.local.to_string(),
+                    _ => String::new(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Document order, not grouped by selector
+        assert_eq!(tags("h3, h1, h2"), vec!["h2", "h1", "h3"]);
+        // Nodes matching several selectors are returned once
+        assert_eq!(tags("div, .container"), vec!["div", "div", "span"]);
+        assert_eq!(select_count(&dom.tree, "div, .container"), 3);
+        assert_eq!(tags(".container > h1,div p , span[class='a,b']"), vec!["h1", "p"]);
+
+        let selector = Selector::parse("div p, h1").unwrap();
+        assert!(selector.matches(&select(&dom.tree, "h1").remove(0)));
+        assert_eq!(
+            Selector::parse("h1, #a, .b").unwrap().specificity(),
+            (1, 0, 0)
+        );
+
+        for selector in ["h1,", ", h1", "h1,,h2", "h1, ,h2"] {
+            assert_eq!(
+                Selector::parse(selector),
+                Err(SelectorError::UnexpectedToken(",".to_string())),
+                "{}",
+                selector
+            );
+        }
+        assert_eq!(Selector::parse(" "), Err(SelectorError::Empty));
+    }
+
+    #[test]
+    fn test_select_first() {
+        let tree = create_tree();
+        for selector in [".item", "div", "div p", "span, p", "#root", "[class~=active]"] {
+            let first = select_first(&tree, selector).unwrap();
+            assert!(
+                first.same_node(&select(&tree, selector)[0]),
+                "selector: {:?}",
+                selector
+            );
+        }
+        assert!(select_first(&tree, "table").is_none());
+        assert!(select_first(&tree, "[").is_none());
+    }
+
+    #[test]
+    fn test_select_scope() {
+        let tree = create_tree();
+        let div = tree.children.borrow()[1].clone();
+
+        let selector = Selector::parse(":scope > .item").unwrap();
+        assert_eq!(selector.select_scoped(&tree).len(), 3);
+        assert!(selector.select_scoped(&div).is_empty());
+
+        // Ancestors outside of the scope are ignored
+        let selector = Selector::parse("#root p").unwrap();
+        assert_eq!(selector.select(&tree).len(), 1);
+        assert!(selector.select_scoped(&div).is_empty());
+        assert!(selector.matches(&div.children.borrow()[0]));
+
+        let p = div.children.borrow()[0].clone();
+        let selector = Selector::parse(":scope > p").unwrap();
+        assert!(selector.matches_scoped(&p, &div));
+        assert!(!selector.matches_scoped(&p, &tree));
+        assert!(!selector.matches(&p));
+
+        // Tree-wide queries have no scope
+        assert!(select(&tree, ":scope > .item").is_empty());
+    }
+
+    #[test]
+    fn test_selector_attributes() {
+        let segments = parse_selector("a[href][rel~=nofollow] [data-type='an article']");
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].element, Some("a".to_string()));
+        assert_eq!(
+            segments[0].attributes,
+            vec![
+                ("href".to_string(), None, AttrMatch::Exact),
+                ("rel".to_string(), Some("nofollow".to_string()), AttrMatch::ContainsWord),
+            ]
+        );
+        assert_eq!(
+            segments[1].attributes,
+            vec![("data-type".to_string(), Some("an article".to_string()), AttrMatch::Exact)]
+        );
+    }
+
+    #[test]
+    fn test_select_attribute_contains_word() {
+        let node = Node::new_element("a", &[("rel", "nofollow noopener")]);
+
+        assert_eq!(select(&node, "[rel~=noopener]").len(), 1);
+        assert_eq!(select(&node, "a[rel~=nofollow]").len(), 1);
+        assert_eq!(select(&node, "[rel]").len(), 1);
+        assert!(select(&node, "[rel~=noop]").is_empty());
+        assert!(select(&node, "[rel=noopener]").is_empty());
+        assert!(select(&node, "[href]").is_empty());
+    }
+
+    #[test]
+    fn test_select_attribute_operators() {
+        let dom = crate::parse::parse_html(
+            r#"<a href="https://example.com/a.pdf">1</a><a href="http://example.com/b">2</a>
+            <a href="/docs/c.pdf" title='say "hi"'>3</a><a>4</a>"#,
+        );
+        let texts = |selector: &str| {
+            select(&dom.tree, selector)
+                .iter()
+                .map(crate::node::text_content)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(texts("a[href^=\"https\"]"), vec!["1"]);
+        assert_eq!(texts("a[href^=http]"), vec!["1", "2"]);
+        assert_eq!(texts("a[href$='.pdf']"), vec!["1", "3"]);
+        assert_eq!(texts("a[href*=example]"), vec!["1", "2"]);
+        assert_eq!(texts("[title='say \"hi\"']"), vec!["3"]);
+        assert_eq!(texts("[title*='\"hi']"), vec!["3"]);
+        assert_eq!(texts("a[href]"), vec!["1", "2", "3"]);
+
+        // Empty values match nothing, and `=` inside a quoted value is part of the value
+        assert!(texts("a[href^='']").is_empty());
+        assert!(texts("a[href*=\"\"]").is_empty());
+        let node = Node::new_element("a", &[("href", "?a=b~=c")]);
+        assert_eq!(select(&node, "[href='?a=b~=c']").len(), 1);
+        assert_eq!(select(&node, "[href$='b~=c']").len(), 1);
+
+        assert_eq!(
+            parse_selector("[a^=x][b$=y][c*=z]")[0].attributes,
+            vec![
+                ("a".to_string(), Some("x".to_string()), AttrMatch::Prefix),
+                ("b".to_string(), Some("y".to_string()), AttrMatch::Suffix),
+                ("c".to_string(), Some("z".to_string()), AttrMatch::Substring),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_count() {
+        let tree = create_tree();
+        for selector in [
+            "div", "span", ".item", "#root", "div.active", "div p", "div span", "[class]",
+            "[class~=active]", "table", "", "   ",
+        ] {
+            assert_eq!(
+                select_count(&tree, selector),
+                select(&tree, selector).len(),
+                "selector: {:?}",
+                selector
+            );
+        }
+        assert_eq!(Selector::parse(".item").unwrap().count(&tree), 3);
+    }
+
+    #[test]
+    fn test_selector_errors() {
+        let cases = [
+            ("", SelectorError::Empty),
+            ("   ", SelectorError::Empty),
+            ("a[href", SelectorError::UnclosedBracket),
+            ("a[title='x]", SelectorError::UnclosedBracket),
+            ("[=value]", SelectorError::MissingAttributeName),
+            ("[ ]", SelectorError::MissingAttributeName),
+            ("a]", SelectorError::UnexpectedToken("]".to_string())),
+            ("[href]a", SelectorError::UnexpectedToken("a".to_string())),
+            ("[a[b]]", SelectorError::UnexpectedToken("[".to_string())),
+            ("[title=a b]", SelectorError::UnexpectedToken("a b".to_string())),
+            ("[title=]", SelectorError::UnexpectedToken("".to_string())),
+            ("[data x]", SelectorError::UnexpectedToken("data x".to_string())),
+        ];
+
+        let tree = create_tree();
+        for (selector, error) in cases {
+            assert_eq!(Selector::parse(selector), Err(error), "selector: {:?}", selector);
+            assert!(select(&tree, selector).is_empty(), "selector: {:?}", selector);
+            assert_eq!(select_count(&tree, selector), 0, "selector: {:?}", selector);
+        }
+
+        assert!(Selector::parse("[title='a b']").is_ok());
+        assert!(Selector::parse("[title=\"]\"]").is_ok());
+    }
+
+    #[test]
+    fn test_try_select() {
+        let tree = create_tree();
+        let cases = [
+            ("", SelectorError::Empty),
+            (">>>", SelectorError::UnexpectedToken(">".to_string())),
+            ("div >", SelectorError::UnexpectedToken(">".to_string())),
+            ("div,", SelectorError::UnexpectedToken(",".to_string())),
+            ("[unclosed", SelectorError::UnclosedBracket),
+            ("li:nth-child(2", SelectorError::UnclosedParenthesis),
+            ("a:hover", SelectorError::UnknownPseudo("hover".to_string())),
+        ];
+        for (selector, error) in cases {
+            assert_eq!(try_select(&tree, selector), Err(error), "selector: {:?}", selector);
+            assert!(select(&tree, selector).is_empty(), "selector: {:?}", selector);
+        }
+
+        let results = try_select(&tree, ".item").unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().zip(select(&tree, ".item")).all(|(a, b)| a.same_node(&b)));
+        assert_eq!(try_select(&tree, "nothing"), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_select_first_and_last_of_type() {
+        use crate::parse::parse_html;
+
+        let dom = parse_html(
+            "<section><h2>Title</h2><p>First</p><p>Second</p><div>Only</div><p>Last</p></section>",
+        );
+        let text = |node: &Handle| {
+            let child = node.children.borrow()[0].clone();
+            match &child.data {
+                NodeData::Text { text } => text.borrow().to_string(),
+                _ => String::new(),
+            }
+        };
+
+        let first = select(&dom.tree, "section p:first-of-type");
+        assert_eq!(first.iter().map(text).collect::<Vec<_>>(), vec!["First"]);
+
+        let last = select(&dom.tree, "section p:last-of-type");
+        assert_eq!(last.iter().map(text).collect::<Vec<_>>(), vec!["Last"]);
+
+        // The only element of its type is both the first and the last
+        assert_eq!(select(&dom.tree, "div:first-of-type:last-of-type").len(), 1);
+        assert_eq!(select(&dom.tree, "h2:last-of-type").len(), 1);
+    }
+
+    #[test]
+    fn test_selector_unknown_pseudo() {
+        assert_eq!(
+            Selector::parse("a:hover"),
+            Err(SelectorError::UnknownPseudo("hover".to_string()))
+        );
+        assert_eq!(
+            Selector::parse("a:"),
+            Err(SelectorError::UnexpectedToken(":".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_select_default_namespace() {
+        use crate::parse::parse_html;
+        use html5ever::ns;
+
+        let dom = parse_html(
+            "<div><a href='/html'>HTML link</a><svg><a href='/svg'>SVG link</a></svg></div>",
+        );
+        assert_eq!(select(&dom.tree, "a").len(), 2);
+
+        let svg_options = SelectorOptions {
+            default_namespace: Some(ns!(svg)),
+            ..Default::default()
+        };
+        let results = select_with_options(&dom.tree, "a", &svg_options);
+        assert_eq!(results.len(), 1);
+        assert!(matches!(&results[0].data, NodeData::Element { name, .. } if name.ns == ns!(svg)));
+
+        let html_options = SelectorOptions {
+            default_namespace: Some(ns!(html)),
+            ..Default::default()
+        };
+        assert_eq!(select_with_options(&dom.tree, "a", &html_options).len(), 1);
+
+        // Only type selectors are restricted
+        assert_eq!(select_with_options(&dom.tree, "[href]", &svg_options).len(), 2);
+    }
+
+    #[test]
+    fn test_select_namespace_prefix() {
+        use crate::parse::parse_html;
+
+        let dom = parse_html(
+            "<rect id='html'></rect><svg><rect id='svg'/><a href='/svg'>SVG</a></svg>\
+            <math><mi>x</mi></math><a href='/html'>HTML</a>",
+        );
+        let ids = |selector: &str| {
+            select(&dom.tree, selector)
+                .iter()
+                .filter_map(|node| node.get_attribute("id"))
+                .collect::<Vec<_>>()
+        };
+
+        // A bare element selector matches any namespace
+        assert_eq!(ids("rect"), ["html", "svg"]);
+        assert_eq!(ids("svg|rect"), ["svg"]);
+        assert_eq!(ids("SVG|rect"), ["svg"]);
+        assert_eq!(ids("html|rect"), ["html"]);
+        assert_eq!(select(&dom.tree, "math|mi").len(), 1);
+        assert!(select(&dom.tree, "svg|mi").is_empty());
+        assert_eq!(select(&dom.tree, "svg|*").len(), 3);
+        assert_eq!(select(&dom.tree, "svg > svg|a[href]").len(), 1);
+
+        // The prefix takes precedence over the default namespace
+        let svg_options = SelectorOptions {
+            default_namespace: Some(ns!(svg)),
+            ..Default::default()
+        };
+        assert_eq!(select_with_options(&dom.tree, "html|a", &svg_options).len(), 1);
+        assert_eq!(
+            Selector::parse("html|rect").unwrap().specificity(),
+            (0, 0, 1)
+        );
+
+        assert_eq!(
+            Selector::parse("xlink|a"),
+            Err(SelectorError::UnknownNamespace("xlink".to_string()))
+        );
+        assert_eq!(
+            Selector::parse("svg|"),
+            Err(SelectorError::UnexpectedToken("svg|".to_string()))
+        );
+    }
+
     #[test]
     fn test_select_empty_selector() {
         let tree = create_tree();
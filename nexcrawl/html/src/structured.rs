@@ -0,0 +1,66 @@
+//! Extract structured data embedded in scripts
+
+use crate::node::{Handle, NodeData, text_content};
+
+/// Get the JSON of every `<script type="application/ld+json">` in the tree, in document order
+///
+/// The strings are the unparsed script contents with surrounding whitespace trimmed; the
+/// caller deserializes them with the JSON library of their choice. Empty scripts are skipped.
+///
+/// Run this on the parsed tree before preprocessing or sanitizing, since both remove
+/// `<script>` elements.
+pub fn extract_json_ld(tree: &Handle) -> Vec<String> {
+    let mut blocks = Vec::new();
+    collect_json_ld(tree, &mut blocks);
+    blocks
+}
+
+fn collect_json_ld(node: &Handle, blocks: &mut Vec<String>) {
+    if is_json_ld_script(node) {
+        let json = text_content(node);
+        let json = json.trim();
+        if !json.is_empty() {
+            blocks.push(json.to_string());
+        }
+        return;
+    }
+    for child in node.children.borrow().iter() {
+        collect_json_ld(child, blocks);
+    }
+}
+
+fn is_json_ld_script(node: &Handle) -> bool {
+    let NodeData::Element { name, attrs, .. } = &node.data else {
+        return false;
+    };
+    name.local.as_ref() == "script"
+        && attrs.borrow().iter().any(|attr| {
+            attr.name.local.as_ref() == "type"
+                && attr
+                    .value
+                    .trim()
+                    .eq_ignore_ascii_case("application/ld+json")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_html;
+
+    #[test]
+    fn test_extract_json_ld() {
+        let html = r#"<html><head>
+            <script type="application/ld+json">
+                {"@context": "https://schema.org", "@type": "Article", "headline": "A < B"}
+            </script>
+            <script>var notJson = 1;</script>
+            <script type="application/ld+json">   </script>
+            </head><body><p>Text</p></body></html>"#;
+        let dom = parse_html(html);
+        assert_eq!(
+            extract_json_ld(&dom.tree),
+            vec![r#"{"@context": "https://schema.org", "@type": "Article", "headline": "A < B"}"#]
+        );
+    }
+}
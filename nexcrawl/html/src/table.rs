@@ -0,0 +1,145 @@
+//! Rebuild `<table>` elements as grids of cell texts
+
+use crate::node::{Handle, NodeData};
+
+/// Largest `colspan` honored when rebuilding a table, like browsers do
+const MAX_COLSPAN: usize = 1000;
+
+/// Get the rows of a table as a grid of cell texts, each cell written by `cell_text`
+///
+/// The header row comes first: the first row of the `<thead>`, or else the first row. The
+/// other rows follow in document order. Rows without cells are skipped, and rows of nested
+/// tables are not included; their text ends up in the cell containing them. A cell spanning
+/// several columns is followed by empty cells, and short rows are padded with empty cells, so
+/// all the rows have the same length. Returns an empty grid if the table has no cells.
+pub(crate) fn table_grid(
+    table: &Handle,
+    cell_text: &dyn Fn(&Handle) -> String,
+) -> Vec<Vec<String>> {
+    let mut header = None;
+    let mut rows = Vec::new();
+    collect_rows(table, false, cell_text, &mut header, &mut rows);
+    if let Some(header) = header {
+        rows.insert(0, header);
+    }
+
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    for row in rows.iter_mut() {
+        row.resize(width, String::new());
+    }
+    rows
+}
+
+fn collect_rows(
+    node: &Handle,
+    in_head: bool,
+    cell_text: &dyn Fn(&Handle) -> String,
+    header: &mut Option<Vec<String>>,
+    rows: &mut Vec<Vec<String>>,
+) {
+    for child in node.children.borrow().iter() {
+        let NodeData::Element { name, .. } = &child.data else {
+            continue;
+        };
+
+        match name.local.as_ref() {
+            "thead" => collect_rows(child, true, cell_text, header, rows),
+            "tbody" | "tfoot" => collect_rows(child, false, cell_text, header, rows),
+            "tr" => {
+                let mut cells = Vec::new();
+                for cell in child.children.borrow().iter() {
+                    if !matches!(&cell.data, NodeData::Element { name, .. } if matches!(name.local.as_ref(), "td" | "th"))
+                    {
+                        continue;
+                    }
+                    cells.push(cell_text(cell));
+
+                    let span = cell
+                        .get_attribute("colspan")
+                        .and_then(|span| span.trim().parse::<usize>().ok())
+                        .unwrap_or(1)
+                        .clamp(1, MAX_COLSPAN);
+                    cells.extend(std::iter::repeat_n(String::new(), span - 1));
+                }
+
+                if cells.is_empty() {
+                    continue;
+                }
+                if in_head && header.is_none() {
+                    *header = Some(cells);
+                } else {
+                    rows.push(cells);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Render a grid from [`table_grid`] as a Markdown pipe table, with the first row as header
+///
+/// `|` in the cells is escaped so it does not break the rows. Returns an empty string if the
+/// grid is empty.
+pub(crate) fn pipe_table(grid: &[Vec<String>]) -> String {
+    let Some((header, body)) = grid.split_first() else {
+        return String::new();
+    };
+
+    let row = |cells: &[String]| {
+        let cells: Vec<_> = cells.iter().map(|cell| cell.replace('|', "\\|")).collect();
+        format!("| {} |", cells.join(" | "))
+    };
+    let mut lines = vec![row(header), row(&vec!["---".to_string(); header.len()])];
+    lines.extend(body.iter().map(|cells| row(cells)));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::text_content;
+    use crate::parse::parse_html;
+    use crate::selector::select;
+
+    fn grid(html: &str) -> Vec<Vec<String>> {
+        let dom = parse_html(html);
+        let table = select(&dom.tree, "table").remove(0);
+        table_grid(&table, &|cell| text_content(cell))
+    }
+
+    #[test]
+    fn test_table_grid() {
+        // The <thead> row comes first, spans and short rows are padded, empty rows are skipped
+        let rows = grid(
+            "<table><tbody><tr><td colspan=2>a</td></tr><tr></tr></tbody>\
+            <thead><tr><th>x</th><th>y</th><th>z</th></tr><tr><td>w</td></tr></thead>\
+            <tr><td>b</td></tr></table>",
+        );
+        assert_eq!(
+            rows,
+            [["x", "y", "z"], ["a", "", ""], ["w", "", ""], ["b", "", ""]]
+        );
+
+        // Without a <thead>, the first row is the header
+        let rows = grid("<table><tr><td>1</td><td>2</td></tr><tr><td>3</td></tr></table>");
+        assert_eq!(rows, [["1", "2"], ["3", ""]]);
+    }
+
+    #[test]
+    fn test_table_grid_empty() {
+        assert!(grid("<table><caption>Empty</caption><tr></tr></table>").is_empty());
+    }
+
+    #[test]
+    fn test_pipe_table() {
+        let grid = vec![
+            vec!["a | b".to_string(), "c".to_string()],
+            vec!["d".to_string(), String::new()],
+        ];
+        assert_eq!(
+            pipe_table(&grid),
+            "| a \\| b | c |\n| --- | --- |\n| d |  |"
+        );
+        assert_eq!(pipe_table(&[]), "");
+    }
+}